@@ -0,0 +1,273 @@
+from typing import Dict, List, Optional, Tuple
+
+class AssemblyError(Exception): ...
+class ExecutionError(Exception): ...
+class InstructionLimitError(ExecutionError): ...
+
+class Fault:
+    """An assembly error or a runtime fault, located at the instruction that caused it."""
+
+    @property
+    def kind(self) -> str: ...
+    @property
+    def message(self) -> str: ...
+    @property
+    def pc(self) -> int: ...
+    @property
+    def source(self) -> str: ...
+    @property
+    def file(self) -> Optional[str]:
+        """Source file, when the program was read from one."""
+    @property
+    def line(self) -> Optional[int]:
+        """Source line, starting at 1."""
+    @property
+    def addr(self) -> Optional[int]: ...
+    @property
+    def is_runtime(self) -> bool: ...
+
+InitSpec = str
+"""How r1-r7 and the RAM start: "zero", "random", "random:<seed>", "poison" or "poison:<value>".
+
+"random" alone draws a fresh seed, which `to_json` snapshots keep; "random:<seed>" starts from
+the same state again."""
+
+ConventionSpec = str
+"""Calling convention checked on calls and returns: "default" (link r7, stack pointer r6,
+arguments r1-r3, callee saved r4-r5) or `;` separated settings among `link=<reg>`, `sp=<reg>`,
+`args=<regs>` and `saved=<regs>`, like "link=r7;sp=r6;args=r1-r3;saved=r4,r5"."""
+
+StackSpec = str
+"""Stack region checked on lw and sw through the stack pointer, or a register computed from
+it with add or addi: "default" (r6, from 0xff down to 0) or `;` separated settings among
+`sp=<reg>`, `top=<addr>` and `limit=<addr>`, like "sp=r6;top=0x7f;limit=0x40".
+Accesses below the limit, above the top or writes to the .fill and .space data of the
+program raise StackOverflow, StackUnderflow and StackCollision faults."""
+
+ProtectionSpec = str
+"""Memory regions protected against the program: `;` separated settings among
+`readonly=<first>-<last>,...`, `noaccess=<first>-<last>,...` and `code`, like
+"code;readonly=0x00-0x0f;noaccess=0xf0-0xff". Stores to read-only regions raise
+WriteProtected faults, loads and stores to no-access regions raise AccessViolation faults.
+`code` is the unified-memory mode: the program takes the first memory words, one per
+instruction, followed by its .fill and .space data, and accessing the code words raises
+AccessViolation faults."""
+
+class Risc16:
+    def __init__(
+        self,
+        max_instr: int = 100000,
+        strict: bool = False,
+        arch: str = "IS0",
+        init: InitSpec = "zero",
+    ) -> None: ...
+    @property
+    def arch(self) -> str: ...
+    def to_json(self) -> str:
+        """Serialize registers, pc, memory, counters, labels and architecture."""
+    @staticmethod
+    def from_json(json: str) -> "Risc16":
+        """Restore a Risc16 saved with `to_json`, with the default devices mapped.
+
+        The calling convention, stack and memory protection checks are not saved,
+        set them again on the restored Risc16.
+        """
+    def set_interrupt_handler(self, handler: str) -> None:
+        """Set the label or ROM index where interrupts jump to (IS1 and IS2 only)."""
+    def set_timer_interrupt(self, period: Optional[int]) -> None:
+        """Raise the timer interrupt (line 0) every `period` instructions, None to disable it.
+
+        Raises ValueError if `period` is 0.
+        """
+    def set_convention(self, spec: Optional[ConventionSpec]) -> None:
+        """Check calls and returns against a calling convention, None to stop checking."""
+    def set_stack(self, spec: Optional[StackSpec]) -> None:
+        """Check lw and sw through the stack pointer against a stack region, None to stop checking."""
+    def set_protection(self, spec: Optional[ProtectionSpec]) -> None:
+        """Protect memory regions against the program, None to remove the protection."""
+    def schedule_interrupt(self, line: int, at: int) -> None:
+        """Raise the external interrupt `line` (1 to 7) once `at` instructions have run.
+
+        Raises ValueError for any other line.
+        """
+    def add_device(
+        self, device: object, name: str, first: int, last: Optional[int] = None
+    ) -> None:
+        """Map a Python object as a device on the addresses `first` to `last`.
+
+        The object must provide `read(addr) -> int` and `write(addr, value)`,
+        and may provide `state() -> str` and `reset()`, called before every run.
+        """
+    def run(self, code: str) -> None:
+        """Assemble and run `code` from a zeroed state, keeping the mapped devices but
+        resetting them.
+
+        Faults are reported in `error`, raises AssemblyError if the code cannot be assembled.
+        """
+    def print_state(self, format: str = "hex") -> str:
+        """Pc, instruction count, registers and the memory words changed by the run.
+
+        Registers are written in `format`: "hex" (16 bit two's complement), "unsigned",
+        "signed" or "binary".
+        """
+    def dump_memory(self, start: int = 0, len: int = 256, format: str = "hex,signed") -> str:
+        """`len` memory words from `start`, in rows of 8 with their address, the words in every
+        format of `format` and the labels of the `.fill` and `.space` words in the row.
+
+        `format` lists, separated by commas, any of "hex", "unsigned", "signed" and "binary".
+        """
+    @property
+    def device_states(self) -> Dict[str, str]:
+        """State of the devices that report one, by device name."""
+    @property
+    def registers(self) -> List[int]: ...
+    @property
+    def pc(self) -> int: ...
+    @property
+    def instr_count(self) -> int: ...
+    @property
+    def max_instr(self) -> int: ...
+    @property
+    def labels(self) -> Dict[str, int]: ...
+    @property
+    def buffer(self) -> str: ...
+    @property
+    def strict(self) -> bool: ...
+    @property
+    def error(self) -> Optional[Fault]: ...
+    trace: bool
+    """Write every executed instruction to `buffer`."""
+    @property
+    def uninit_reads(self) -> List[Tuple[int, str, str]]:
+        """First read of each location never written, as (pc, source, location)."""
+    @property
+    def convention_violations(self) -> List[Tuple[int, str]]:
+        """Breaches of the calling convention, as (pc, message)."""
+    @property
+    def backtrace(self) -> List[Tuple[str, int, Optional[int]]]:
+        """Subroutines being executed as guessed from `jalr`, innermost first,
+        as (subroutine, ROM index, source line)."""
+    @property
+    def memory_diff(self) -> Dict[int, Tuple[Optional[str], int, int]]:
+        """RAM words changed by the run, as {address: (label, old value, new value)}."""
+    @property
+    def console(self) -> str:
+        """Text written to the console devices during the run."""
+    @property
+    def devices(self) -> List[Tuple[str, int, int]]:
+        """Mapped devices as (name, first address, last address)."""
+
+DeviceConfig = List[Tuple[str, int]]
+"""(name, address) of the devices to map, among "console", "input" and "timer"."""
+
+def run_from_str_py(
+    max_instr: int,
+    trace: bool,
+    code: str,
+    strict: bool = False,
+    input_queue: List[int] = [],
+    devices: Optional[DeviceConfig] = None,
+    init: InitSpec = "zero",
+    convention: Optional[ConventionSpec] = None,
+    stack: Optional[StackSpec] = None,
+    protection: Optional[ProtectionSpec] = None,
+    arch: str = "IS0",
+) -> Tuple[str, str]:
+    """Assemble and run `code` once.
+
+    Returns the output buffer (trace, warnings, runtime error and console output) and the final state.
+    Raises AssemblyError if the code cannot be assembled.
+    """
+
+def run_py(
+    max_instr: int,
+    trace: bool,
+    code: str,
+    strict: bool = False,
+    input_queue: List[int] = [],
+    devices: Optional[DeviceConfig] = None,
+    init: InitSpec = "zero",
+    convention: Optional[ConventionSpec] = None,
+    stack: Optional[StackSpec] = None,
+    protection: Optional[ProtectionSpec] = None,
+    arch: str = "IS0",
+) -> Risc16:
+    """Assemble and run `code` once.
+
+    Returns the final Risc16 state, with `error` set if the run faulted.
+    Raises AssemblyError if the code cannot be assembled.
+    """
+
+def test_batch_py(
+    max_instr: int,
+    trace: bool,
+    code: str,
+    tests: List[List[Tuple[int, int]]],
+    strict: bool = False,
+    input_queue: List[int] = [],
+    devices: Optional[DeviceConfig] = None,
+    init: InitSpec = "zero",
+    convention: Optional[ConventionSpec] = None,
+    stack: Optional[StackSpec] = None,
+    protection: Optional[ProtectionSpec] = None,
+    arch: str = "IS0",
+) -> List[Risc16]:
+    """Assemble `code` and run it once per test vector, sequentially.
+
+    Each test vector is a list of (register, value) pairs set before running.
+    Returns the final Risc16 state of every run, with `error` set on the faulty ones.
+    """
+
+def test_batch_par_py(
+    max_instr: int,
+    trace: bool,
+    code: str,
+    tests: List[List[Tuple[int, int]]],
+    strict: bool = False,
+    input_queue: List[int] = [],
+    devices: Optional[DeviceConfig] = None,
+    init: InitSpec = "zero",
+    convention: Optional[ConventionSpec] = None,
+    stack: Optional[StackSpec] = None,
+    protection: Optional[ProtectionSpec] = None,
+    arch: str = "IS0",
+) -> List[Risc16]:
+    """Assemble `code` and run it once per test vector, in parallel.
+
+    Each test vector is a list of (register, value) pairs set before running.
+    Returns the final Risc16 state of every run, with `error` set on the faulty ones.
+    """
+
+def load_rom_py(code: str) -> str:
+    """Assemble `code` and return the decoded program, one instruction per line.
+
+    Raises AssemblyError if the code cannot be assembled.
+    """
+
+def format_py(code: str) -> str:
+    """Format `code`, keeping comments and blank lines and aligning labels, mnemonics,
+    operands and trailing comments in columns.
+
+    Raises AssemblyError if the code cannot be assembled.
+    """
+
+def test_batch_seeds_py(
+    max_instr: int,
+    trace: bool,
+    code: str,
+    tests: List[List[Tuple[int, int]]],
+    seeds: List[int],
+    strict: bool = False,
+    input_queue: List[int] = [],
+    devices: Optional[DeviceConfig] = None,
+    convention: Optional[ConventionSpec] = None,
+    stack: Optional[StackSpec] = None,
+    protection: Optional[ProtectionSpec] = None,
+    arch: str = "IS0",
+) -> List[Tuple[Risc16, List[int]]]:
+    """Assemble `code` and run every test vector from zeroed state, then again
+    with random registers and memory for each seed.
+
+    Returns for every test vector the zeroed run and the seeds whose outcome differs,
+    a non empty list hints at a read of an uninitialized register or memory word.
+    """
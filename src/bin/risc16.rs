@@ -0,0 +1,7 @@
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    process::exit(risc16_rs::cli::main(&args));
+}
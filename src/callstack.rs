@@ -0,0 +1,90 @@
+/// A subroutine call, as seen by the shadow call stack.
+#[derive(Debug, Clone)]
+pub(crate) struct Frame {
+    /// ROM index of the calling `jalr`, the subroutine returns just after it.
+    pub(crate) call_site: usize,
+    /// ROM index of the first instruction of the subroutine.
+    pub(crate) target: usize,
+    /// Register holding the return address.
+    pub(crate) link: usize,
+    /// Registers when the call was made.
+    pub(crate) registers: [i16; 8],
+    /// Registers written since the call.
+    pub(crate) written: [bool; 8],
+}
+
+/// What a `jalr` did to the call stack.
+pub(crate) enum Transfer {
+    Call,
+    /// Back to the caller of `frame`, `mismatch` tells how it went wrong.
+    Return {
+        frame: Frame,
+        mismatch: Option<Mismatch>,
+    },
+    Jump,
+}
+
+/// A return that does not match the innermost call.
+pub(crate) enum Mismatch {
+    /// Returned to an outer caller, the inner frames never returned.
+    Skipped(usize),
+    /// Jumped through the link register of the innermost frame, but not to its return address.
+    Elsewhere { expected: usize },
+}
+
+/// Shadow call stack guessed from `jalr`: writing a link register is a call,
+/// jumping to a return address or through the link register of the innermost call is a return.
+/// Any other `jalr` is a plain jump.
+#[derive(Default)]
+pub(crate) struct CallStack {
+    pub(crate) frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Record `jalr link, base` at `pc` jumping to `target`, with `registers` as they
+    /// were before the jump.
+    pub(crate) fn jalr(
+        &mut self,
+        pc: usize,
+        link: usize,
+        base: usize,
+        target: usize,
+        registers: [i16; 8],
+    ) -> Transfer {
+        if link != 0 {
+            self.frames.push(Frame {
+                call_site: pc,
+                target,
+                link,
+                registers,
+                written: [false; 8],
+            });
+            return Transfer::Call;
+        }
+        if let Some(depth) = self.frames.iter().rposition(|f| f.call_site + 1 == target) {
+            let skipped = self.frames.len() - 1 - depth;
+            let frame = self.frames.drain(depth..).next().unwrap();
+            return Transfer::Return {
+                frame,
+                mismatch: Some(Mismatch::Skipped(skipped)).filter(|_| skipped > 0),
+            };
+        }
+        match self.frames.last() {
+            Some(frame) if frame.link == base => {
+                let expected = frame.call_site + 1;
+                Transfer::Return {
+                    frame: self.frames.pop().unwrap(),
+                    mismatch: Some(Mismatch::Elsewhere { expected }),
+                }
+            }
+            _ => Transfer::Jump,
+        }
+    }
+
+    /// Note a register write in the innermost frame.
+    pub(crate) fn written(&mut self, reg: usize) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.written[reg] = true;
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::convention::Convention;
+use crate::dap::DapServer;
+use crate::debugger::Debugger;
+use crate::format::format_source;
+use crate::gdbstub::GdbStub;
+use crate::lsp::LspServer;
+use crate::numbers::NumberFormat;
+use crate::protection::Protection;
+use crate::stack::Stack;
+use crate::{assemble_file, devices, Archtype, InitPolicy, Risc16, RiscResult, Rom, SourceMap};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+
+const USAGE: &str = "\
+Usage: risc16 <command> [options] <file>
+       risc16 dap
+       risc16 lsp
+
+Commands:
+    run      assemble and run the program, then print the final state
+    debug    run the program in an interactive debugger
+    gdb      serve the program to a GDB remote protocol client
+    dap      serve the Debug Adapter Protocol on stdin and stdout, for editors
+    lsp      serve the Language Server Protocol on stdin and stdout, for editors
+    fmt      print the program formatted, or rewrite the file with --write
+
+Options:
+    --strict             range checks at assembly, faults instead of warnings
+    --arch <name>        IS0 (default), IS1 or IS2
+    --max-instr <n>      instruction limit, 100000 by default
+    --trace              run, debug: write every executed instruction to the output
+    --format <name>      run: number format of the final registers: hex (default),
+                         unsigned, signed or binary
+    --init <policy>      initial state: zero (default), random[:seed] or poison[:value],
+                         random alone draws a fresh seed
+    --convention <spec>  check calls against a calling convention: default (link r7,
+                         stack pointer r6, arguments r1-r3, callee saved r4-r5) or
+                         settings like \"link=r7;sp=r6;args=r1-r3;saved=r4,r5\"
+    --stack <spec>       check lw and sw through the stack pointer against a stack region
+                         and the program data: default (r6, from 0xff down to 0) or
+                         settings like \"sp=r6;top=0x7f;limit=0x40\"
+    --protection <spec>  fault on stores to read-only and accesses to no-access regions,
+                         and to the code with code (unified memory): settings like
+                         \"code;readonly=0x00-0x0f;noaccess=0xf0-0xff\"
+    --port <n>           gdb: TCP port to listen on, 1234 by default
+    --stdio              gdb: talk over stdin and stdout instead of TCP
+    --write              fmt: rewrite the file instead of printing it";
+
+/// Options common to every command.
+struct Options {
+    file: Option<String>,
+    strict: bool,
+    arch: Archtype,
+    max_instr: u32,
+    trace: bool,
+    format: NumberFormat,
+    init: InitPolicy,
+    convention: Option<Convention>,
+    stack: Option<Stack>,
+    protection: Option<Protection>,
+    port: u16,
+    stdio: bool,
+    write: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> RiscResult<Options> {
+        let mut options = Options {
+            file: None,
+            strict: false,
+            arch: Archtype::IS0,
+            max_instr: 100000,
+            trace: false,
+            format: NumberFormat::Hex,
+            init: InitPolicy::Zero,
+            convention: None,
+            stack: None,
+            protection: None,
+            port: 1234,
+            stdio: false,
+            write: false,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--strict" => options.strict = true,
+                "--arch" => options.arch = Archtype::from_name(value()?)?,
+                "--max-instr" => options.max_instr = value()?.parse()?,
+                "--trace" => options.trace = true,
+                "--format" => options.format = NumberFormat::from_name(value()?)?,
+                "--init" => options.init = InitPolicy::from_spec(value()?)?,
+                "--convention" => options.convention = Some(Convention::from_spec(value()?)?),
+                "--stack" => options.stack = Some(Stack::from_spec(value()?)?),
+                "--protection" => options.protection = Some(Protection::from_spec(value()?)?),
+                "--port" => options.port = value()?.parse()?,
+                "--stdio" => options.stdio = true,
+                "--write" => options.write = true,
+                opt if opt.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", opt).into())
+                }
+                _ if options.file.is_none() => options.file = Some(arg.clone()),
+                _ => return Err(format!("Unexpected argument: {}", arg).into()),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Read and assemble the program file.
+    fn program(&self) -> RiscResult<(Rom, HashMap<String, usize>, SourceMap)> {
+        let file = self.file.as_ref().ok_or("Missing program file")?;
+        assemble_file(file, self.strict, self.arch)
+    }
+
+    /// A processor with the default devices, ready to run the program of `source_map`.
+    fn processor(&self, source_map: SourceMap) -> Risc16 {
+        let mut proc = Risc16::new(self.arch, self.max_instr, self.strict);
+        proc.devices = devices::default_devices(&[]);
+        proc.set_init(self.init);
+        proc.source_map = source_map;
+        proc.trace = self.trace;
+        proc.convention = self.convention.clone();
+        proc.stack = self.stack.clone();
+        proc.protection = self.protection.clone();
+        proc
+    }
+}
+
+/// Entry point of the `risc16` binary, returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+    let (command, rest) = match args {
+        [_, command, rest @ ..] => (command.as_str(), rest),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    if command == "help" || command == "--help" || command == "-h" {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let options = match Options::parse(rest) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let result = match command {
+        "run" => run(&options),
+        "debug" => debug(&options),
+        "gdb" => gdb(&options),
+        "dap" => dap(),
+        "lsp" => lsp(),
+        "fmt" => fmt(&options),
+        _ => {
+            eprintln!("Unknown command: {}\n\n{}", command, USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn run(options: &Options) -> RiscResult<i32> {
+    let (rom, labels, source_map) = options.program()?;
+    let mut proc = options.processor(source_map);
+    if let Err(e) = proc.execute(&rom, &labels) {
+        proc.fail(e);
+    }
+    print!("{}", proc.buffer);
+    let console = proc.console();
+    if !console.is_empty() {
+        println!("Console:\n{}", console);
+    }
+    print!("{}", proc.print_state(false, options.format)?);
+    Ok(if proc.error.is_some() { 1 } else { 0 })
+}
+
+fn debug(options: &Options) -> RiscResult<i32> {
+    let (rom, labels, source_map) = options.program()?;
+    let mut debugger = Debugger::new(options.processor(source_map), rom, labels);
+    let stdin = io::stdin();
+    debugger.repl(stdin.lock(), &mut io::stdout())?;
+    Ok(0)
+}
+
+fn gdb(options: &Options) -> RiscResult<i32> {
+    let (rom, labels, source_map) = options.program()?;
+    let mut stub = GdbStub::new(options.processor(source_map), rom, labels);
+    if options.stdio {
+        let stdin = io::stdin();
+        stub.serve(stdin.lock(), &mut io::stdout())?;
+    } else {
+        let listener = TcpListener::bind(("127.0.0.1", options.port))?;
+        eprintln!("Waiting for GDB on port {}", options.port);
+        let (stream, _) = listener.accept()?;
+        stub.serve(io::BufReader::new(stream.try_clone()?), &mut &stream)?;
+    }
+    Ok(0)
+}
+
+fn dap() -> RiscResult<i32> {
+    // the program and its options come with the launch request
+    let stdin = io::stdin();
+    DapServer::new().serve(stdin.lock(), &mut io::stdout())?;
+    Ok(0)
+}
+
+fn lsp() -> RiscResult<i32> {
+    let stdin = io::stdin();
+    LspServer::new().serve(stdin.lock(), &mut io::stdout())?;
+    Ok(0)
+}
+
+fn fmt(options: &Options) -> RiscResult<i32> {
+    let file = options.file.as_ref().ok_or("Missing program file")?;
+    let formatted = format_source(&fs::read_to_string(file)?)?;
+    if options.write {
+        fs::write(file, formatted)?;
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(0)
+}
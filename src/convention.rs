@@ -0,0 +1,70 @@
+use crate::{process_register, spec_settings, RiscResult};
+
+/// Register usage subroutines must follow, checked on every call and return.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Convention {
+    /// Register calls write the return address to.
+    pub(crate) link: usize,
+    pub(crate) stack_pointer: usize,
+    /// Registers a subroutine may read before writing them.
+    pub(crate) arguments: Vec<usize>,
+    /// Registers a subroutine must give back unchanged.
+    pub(crate) callee_saved: Vec<usize>,
+}
+
+impl Default for Convention {
+    fn default() -> Convention {
+        Convention {
+            link: 7,
+            stack_pointer: 6,
+            arguments: vec![1, 2, 3],
+            callee_saved: vec![4, 5],
+        }
+    }
+}
+
+impl Convention {
+    /// Parse "default" or settings among `link=<reg>`, `sp=<reg>`, `args=<regs>` and
+    /// `saved=<regs>`, like "link=r7;sp=r6;args=r1-r3;saved=r4,r5".
+    pub(crate) fn from_spec(spec: &str) -> RiscResult<Convention> {
+        let mut convention = Convention::default();
+        if spec.trim() == "default" {
+            return Ok(convention);
+        }
+        for (key, value) in spec_settings(spec) {
+            let value = value.ok_or_else(|| format!("Bad calling convention setting: {}", key))?;
+            match key {
+                "link" => convention.link = process_register(value)?,
+                "sp" => convention.stack_pointer = process_register(value)?,
+                "args" => convention.arguments = registers(value)?,
+                "saved" => convention.callee_saved = registers(value)?,
+                _ => return Err(format!("Unknown calling convention setting: {}", key).into()),
+            }
+        }
+        Ok(convention)
+    }
+
+    /// A subroutine may read these registers before writing them.
+    pub(crate) fn may_read(&self, reg: usize) -> bool {
+        // callee saved registers are read to save them on the stack
+        reg == 0
+            || reg == self.link
+            || reg == self.stack_pointer
+            || self.arguments.contains(&reg)
+            || self.callee_saved.contains(&reg)
+    }
+}
+
+/// Registers separated by commas, `r1-r3` standing for r1, r2 and r3.
+fn registers(list: &str) -> RiscResult<Vec<usize>> {
+    let mut regs = Vec::new();
+    for item in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                regs.extend(process_register(first.trim())?..=process_register(last.trim())?)
+            }
+            None => regs.push(process_register(item)?),
+        }
+    }
+    Ok(regs)
+}
@@ -0,0 +1,626 @@
+use crate::debugger::show;
+use crate::{
+    assemble_file, devices, resolve_imm, Archtype, Args, InitPolicy, Risc16, RiscResult, Rom,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const MEMORY_REF: i64 = 2;
+/// Longest message body accepted, far more than any RiSC-16 program needs. Lengths come
+/// from the client, a larger one is refused rather than allocated.
+const MAX_MESSAGE_LENGTH: usize = 1 << 24;
+
+/// How far a resume request runs.
+#[derive(Clone, Copy)]
+enum Resume {
+    Continue,
+    Instruction,
+    /// Until the pc reaches the given ROM index, to run over a call.
+    Until(usize),
+    /// Until a `jalr` returns through r0.
+    Return,
+}
+
+/// Why the program gave control back to the client.
+enum Stop {
+    Step,
+    Breakpoint,
+    Exception(String),
+    Exited,
+}
+
+/// A program loaded by a `launch` request.
+struct Session {
+    proc: Risc16,
+    rom: Rom,
+    labels: HashMap<String, usize>,
+    path: String,
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    /// Set once the program halted or faulted, resuming then ends the session.
+    finished: bool,
+    buffer_shown: usize,
+    console_shown: usize,
+}
+
+impl Session {
+    fn launch(args: &Value) -> RiscResult<Session> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("Missing program in launch arguments")?
+            .to_string();
+        let strict = args["strict"].as_bool().unwrap_or(false);
+        let arch = Archtype::from_name(args["arch"].as_str().unwrap_or("IS0"))?;
+        let max_instr = args["maxInstr"].as_u64().unwrap_or(100000) as u32;
+        let init = InitPolicy::from_spec(args["init"].as_str().unwrap_or("zero"))?;
+
+        let (rom, labels, source_map) = assemble_file(&path, strict, arch)?;
+        let mut proc = Risc16::new(arch, max_instr, strict);
+        proc.devices = devices::default_devices(&[]);
+        proc.set_init(init);
+        proc.source_map = source_map;
+        proc.labels = labels.clone();
+        proc.prepare_run();
+        Ok(Session {
+            proc,
+            rom,
+            labels,
+            path,
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            finished: false,
+            buffer_shown: 0,
+            console_shown: 0,
+        })
+    }
+
+    /// ROM index of the first instruction at or after `line`.
+    fn index_of_line(&self, line: usize) -> Option<usize> {
+        self.proc.source_map.lines.iter().position(|l| *l >= line)
+    }
+
+    fn line_of(&self, index: usize) -> usize {
+        let lines = &self.proc.source_map.lines;
+        match lines.get(index) {
+            Some(line) => *line,
+            None => lines.last().copied().unwrap_or(1),
+        }
+    }
+
+    fn run(&mut self, resume: Resume) -> Stop {
+        loop {
+            let returning = matches!(
+                self.rom.get(self.proc.pc),
+                Some((instr, Args::A23(args))) if instr == "jalr" && args[0] == 0
+            );
+            match self.proc.step(&self.rom) {
+                Ok(true) => (),
+                Ok(false) => return Stop::Exited,
+                Err(e) => {
+                    let message = e.to_string();
+                    self.proc.fail(e);
+                    return Stop::Exception(message);
+                }
+            }
+            let done = match resume {
+                Resume::Continue => false,
+                Resume::Instruction => true,
+                Resume::Until(index) => self.proc.pc == index,
+                Resume::Return => returning,
+            };
+            if done {
+                return Stop::Step;
+            } else if self.breakpoints.contains(&self.proc.pc) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    /// Text written to the output buffer and the console since the last call.
+    fn new_output(&mut self) -> String {
+        let mut text = self.proc.buffer[self.buffer_shown..].to_string();
+        self.buffer_shown = self.proc.buffer.len();
+        let console = self.proc.console();
+        text.push_str(&console[self.console_shown..]);
+        self.console_shown = console.len();
+        text
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let variable = |name: String, val: i16| {
+            json!({
+                "name": name,
+                "value": show(val),
+                "variablesReference": 0,
+            })
+        };
+        match reference {
+            REGISTERS_REF => self
+                .proc
+                .registers
+                .iter()
+                .enumerate()
+                .map(|(i, val)| variable(format!("r{}", i), *val))
+                .chain(std::iter::once(variable(
+                    "pc".to_string(),
+                    self.proc.pc as i16,
+                )))
+                .collect(),
+            MEMORY_REF => self
+                .proc
+                .ram
+                .iter()
+                .enumerate()
+                .map(|(addr, val)| variable(format!("mem[{:#06x}]", addr), *val))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_variable(&mut self, name: &str, value: &str) -> Result<i16, String> {
+        let val =
+            resolve_imm(value, &self.labels).ok_or_else(|| format!("Bad value: {}", value))? as i16;
+        if name == "pc" {
+            self.proc.pc = val as u16 as usize;
+        } else if let Some(addr) = name
+            .strip_prefix("mem[0x")
+            .and_then(|a| a.strip_suffix(']'))
+        {
+            let addr = usize::from_str_radix(addr, 16).map_err(|e| e.to_string())?;
+            *self.proc.ram.get_mut(addr).ok_or("Outside RAM")? = val;
+            self.proc.ram_written[addr] = true;
+        } else {
+            let reg = crate::process_register(name).map_err(|e| e.to_string())?;
+            if reg == 0 {
+                return Err("r0 is hardwired to 0".to_string());
+            }
+            self.proc.write_reg(reg, val).map_err(|e| e.to_string())?;
+        }
+        Ok(val)
+    }
+}
+
+/// Debug Adapter Protocol server, editors talk to it over stdin and stdout.
+pub(crate) struct DapServer {
+    seq: i64,
+    session: Option<Session>,
+    /// Lines requested by the last setBreakpoints, kept until a program is launched.
+    breakpoint_lines: Vec<usize>,
+    configured: bool,
+    started: bool,
+}
+
+impl DapServer {
+    pub(crate) fn new() -> DapServer {
+        DapServer {
+            seq: 0,
+            session: None,
+            breakpoint_lines: Vec::new(),
+            configured: false,
+            started: false,
+        }
+    }
+
+    /// Answer requests until `disconnect` or the end of the input.
+    pub(crate) fn serve(
+        &mut self,
+        mut input: impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        while let Some(request) = read_message(&mut input)? {
+            let command = request["command"].as_str().unwrap_or("").to_string();
+            let mut events = Vec::new();
+            let result = self.handle(&command, &request["arguments"], &mut events);
+            self.seq += 1;
+            let mut response = json!({
+                "seq": self.seq,
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(body) => response["body"] = body,
+                Err(message) => response["message"] = json!(message),
+            }
+            write_message(out, &response)?;
+            for (event, body) in events {
+                self.seq += 1;
+                let event = json!({"seq": self.seq, "type": "event", "event": event, "body": body});
+                write_message(out, &event)?;
+            }
+            if command == "disconnect" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Body of the response, events to send after it are pushed to `events`.
+    fn handle(
+        &mut self,
+        command: &str,
+        args: &Value,
+        events: &mut Vec<(&'static str, Value)>,
+    ) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                events.push(("initialized", json!({})));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsSetVariable": true,
+                }))
+            }
+            "launch" => {
+                let mut session = Session::launch(args).map_err(|e| e.to_string())?;
+                session.breakpoints = self
+                    .breakpoint_lines
+                    .iter()
+                    .filter_map(|line| session.index_of_line(*line))
+                    .collect();
+                self.session = Some(session);
+                self.start(events);
+                Ok(json!({}))
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.start(events);
+                Ok(json!({}))
+            }
+            "setBreakpoints" => {
+                self.breakpoint_lines = args["breakpoints"]
+                    .as_array()
+                    .map(|bps| {
+                        bps.iter()
+                            .filter_map(|bp| bp["line"].as_u64())
+                            .map(|line| line as usize)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let breakpoints = match &mut self.session {
+                    Some(session) => {
+                        session.breakpoints.clear();
+                        let mut breakpoints = Vec::new();
+                        for line in &self.breakpoint_lines {
+                            match session.index_of_line(*line) {
+                                Some(index) => {
+                                    session.breakpoints.insert(index);
+                                    let line = session.line_of(index);
+                                    breakpoints.push(json!({"verified": true, "line": line}));
+                                }
+                                None => breakpoints.push(json!({"verified": false, "line": line})),
+                            }
+                        }
+                        breakpoints
+                    }
+                    None => self
+                        .breakpoint_lines
+                        .iter()
+                        .map(|line| json!({"verified": false, "line": line}))
+                        .collect(),
+                };
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "risc16"}]})),
+            "stackTrace" => {
+                let session = self.session()?;
+                // one frame per subroutine of the shadow call stack, innermost first
+                let frames: Vec<Value> = session
+                    .proc
+                    .backtrace()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, (name, pc))| {
+                        json!({
+                            "id": id,
+                            "name": name,
+                            "line": session.line_of(pc),
+                            "column": 1,
+                            "source": {"path": session.path},
+                        })
+                    })
+                    .collect();
+                Ok(json!({"totalFrames": frames.len(), "stackFrames": frames}))
+            }
+            "scopes" => Ok(json!({"scopes": [
+                {"name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false},
+                {"name": "Memory", "variablesReference": MEMORY_REF, "expensive": true},
+            ]})),
+            "variables" => {
+                let reference = args["variablesReference"].as_i64().unwrap_or(0);
+                Ok(json!({"variables": self.session()?.variables(reference)}))
+            }
+            "setVariable" => {
+                let name = args["name"].as_str().unwrap_or("");
+                let value = args["value"].as_str().unwrap_or("");
+                let val = self.session_mut()?.set_variable(name, value)?;
+                Ok(json!({ "value": show(val) }))
+            }
+            "continue" => {
+                self.resume(Resume::Continue, events)?;
+                Ok(json!({"allThreadsContinued": true}))
+            }
+            "next" => {
+                let session = self.session()?;
+                let resume = match session.rom.get(session.proc.pc) {
+                    Some((instr, Args::A23(args))) if instr == "jalr" && args[0] != 0 => {
+                        Resume::Until(session.proc.pc + 1)
+                    }
+                    _ => Resume::Instruction,
+                };
+                self.resume(resume, events)?;
+                Ok(json!({}))
+            }
+            "stepIn" => {
+                self.resume(Resume::Instruction, events)?;
+                Ok(json!({}))
+            }
+            "stepOut" => {
+                self.resume(Resume::Return, events)?;
+                Ok(json!({}))
+            }
+            // execution only happens while handling a request, there is nothing to pause
+            "pause" | "disconnect" => Ok(json!({})),
+            _ => Err(format!("Unsupported request: {}", command)),
+        }
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| "No program launched".to_string())
+    }
+
+    fn session_mut(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "No program launched".to_string())
+    }
+
+    /// Start the program once it is launched and the client sent its configuration.
+    fn start(&mut self, events: &mut Vec<(&'static str, Value)>) {
+        let stop_on_entry = match &self.session {
+            Some(session) if self.configured && !self.started => session.stop_on_entry,
+            _ => return,
+        };
+        self.started = true;
+        if stop_on_entry {
+            events.push(("stopped", json!({"reason": "entry", "threadId": THREAD_ID})));
+        } else {
+            // the session exists, resuming cannot fail
+            self.resume(Resume::Continue, events).unwrap();
+        }
+    }
+
+    fn resume(
+        &mut self,
+        resume: Resume,
+        events: &mut Vec<(&'static str, Value)>,
+    ) -> Result<(), String> {
+        let session = self.session_mut()?;
+        if session.finished {
+            let code = if session.proc.error.is_some() { 1 } else { 0 };
+            events.push(("exited", json!({ "exitCode": code })));
+            events.push(("terminated", json!({})));
+            return Ok(());
+        }
+        let stop = session.run(resume);
+        let output = session.new_output();
+        if !output.is_empty() {
+            events.push(("output", json!({"category": "stdout", "output": output})));
+        }
+        let stopped = |reason: &str| json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true});
+        match stop {
+            Stop::Step => events.push(("stopped", stopped("step"))),
+            Stop::Breakpoint => events.push(("stopped", stopped("breakpoint"))),
+            Stop::Exception(message) => {
+                // stay stopped on the faulty instruction so that the state can be inspected
+                session.finished = true;
+                let mut body = stopped("exception");
+                body["description"] = json!(message);
+                body["text"] = json!(message);
+                events.push(("stopped", body));
+            }
+            Stop::Exited => {
+                session.finished = true;
+                events.push(("exited", json!({"exitCode": 0})));
+                events.push(("terminated", json!({})));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Next message, None at the end of the input. The language server uses the same framing.
+pub(crate) fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Message of {} bytes, the limit is {}",
+                length, MAX_MESSAGE_LENGTH
+            ),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+pub(crate) fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: Value) -> String {
+        let body = value.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Messages of the server answering `requests`, as (command or event name, message).
+    fn serve(requests: &[(&str, Value)]) -> Vec<(String, Value)> {
+        let input: String = requests
+            .iter()
+            .enumerate()
+            .map(|(seq, (command, arguments))| {
+                message(json!({"seq": seq + 1, "type": "request", "command": command, "arguments": arguments}))
+            })
+            .collect();
+        let mut out = Vec::new();
+        DapServer::new().serve(input.as_bytes(), &mut out).unwrap();
+        let mut out = out.as_slice();
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut out).unwrap() {
+            let name = match message["type"].as_str() {
+                Some("event") => &message["event"],
+                _ => &message["command"],
+            };
+            messages.push((name.as_str().unwrap().to_string(), message));
+        }
+        messages
+    }
+
+    fn frames(message: &Value) -> Vec<(&str, u64)> {
+        message["body"]["stackFrames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                (
+                    frame["name"].as_str().unwrap(),
+                    frame["line"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scripted_session() {
+        let program = format!("{}/tests/calls.txt", env!("CARGO_MANIFEST_DIR"));
+        let messages = serve(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "program": program })),
+            (
+                "setBreakpoints",
+                json!({"source": {"path": program}, "breakpoints": [{"line": 22}, {"line": 99}]}),
+            ),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({"threadId": THREAD_ID})),
+            ("continue", json!({"threadId": THREAD_ID})),
+            ("stackTrace", json!({"threadId": THREAD_ID})),
+            ("continue", json!({"threadId": THREAD_ID})),
+            ("disconnect", json!({})),
+        ]);
+        let names: Vec<&str> = messages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "initialize",
+                "initialized",
+                "launch",
+                "setBreakpoints",
+                "configurationDone",
+                "output",
+                "stopped",
+                "stackTrace",
+                "continue",
+                "output",
+                "stopped",
+                "stackTrace",
+                "continue",
+                "exited",
+                "terminated",
+                "disconnect",
+            ]
+        );
+        assert!(messages
+            .iter()
+            .all(|(_, m)| m["type"] == "event" || m["success"] == true));
+        // responses follow the requests, sequence numbers keep increasing
+        let seqs: Vec<u64> = messages
+            .iter()
+            .map(|(_, m)| m["seq"].as_u64().unwrap())
+            .collect();
+        assert_eq!(seqs, (1..=16).collect::<Vec<_>>());
+        assert_eq!(messages[3].1["request_seq"], 3);
+
+        let breakpoints = &messages[3].1["body"]["breakpoints"];
+        assert_eq!(breakpoints[0], json!({"verified": true, "line": 22}));
+        assert_eq!(breakpoints[1], json!({"verified": false, "line": 99}));
+        let output = messages[5].1["body"]["output"].as_str().unwrap();
+        assert!(output.contains("mismatched return"));
+        assert_eq!(messages[6].1["body"]["reason"], "breakpoint");
+        assert_eq!(
+            frames(&messages[7].1),
+            [("oob", 22), ("outer", 19), ("back", 15)]
+        );
+        let output = messages[9].1["body"]["output"].as_str().unwrap();
+        assert!(output.contains("Backtrace:"));
+        let stopped = &messages[10].1["body"];
+        assert_eq!(stopped["reason"], "exception");
+        let text = stopped["text"].as_str().unwrap();
+        assert!(text.starts_with("Index of memory out of bounds. (sw 1,3,0, instruction 14"));
+        assert_eq!(frames(&messages[11].1)[0], ("oob", 23));
+        assert_eq!(messages[13].1["body"]["exitCode"], 1);
+    }
+
+    #[test]
+    fn failed_requests() {
+        let messages = serve(&[
+            ("stackTrace", json!({})),
+            ("launch", json!({})),
+            ("launch", json!({"program": "/nonexistent.txt"})),
+            ("evaluate", json!({})),
+        ]);
+        let errors: Vec<&str> = messages
+            .iter()
+            .inspect(|(_, m)| assert_eq!(m["success"], false))
+            .map(|(_, m)| m["message"].as_str().unwrap())
+            .collect();
+        assert_eq!(errors[0], "No program launched");
+        assert_eq!(errors[1], "Missing program in launch arguments");
+        assert!(errors[2].contains("No such file"));
+        assert_eq!(errors[3], "Unsupported request: evaluate");
+    }
+
+    #[test]
+    fn message_lengths() {
+        let mut input = "Content-Length: 99999999999\r\n\r\n{}".as_bytes();
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut input = "Content-Length: 10\r\n\r\n{}".as_bytes();
+        assert!(read_message(&mut input).is_err());
+        // headers other than the length are skipped
+        let input = message(json!({"a": 1}));
+        let input = format!("Content-Type: x\r\n{}", input);
+        let mut input = input.as_bytes();
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({"a": 1})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,543 @@
+use crate::numbers::NumberFormat;
+use crate::{devices, format_code, process_register, resolve_imm, Args, Risc16, Rom};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Instructions shown before and after the pc by `disas`.
+const DISAS_CONTEXT: usize = 4;
+
+const HELP: &str = "\
+step [n]              execute n instructions (1 by default)
+next                  like step, but run over calls made with jalr
+continue              run until a breakpoint, a watchpoint, HALT or a fault
+break [where]         stop before this instruction, list breakpoints without argument
+delete [where]        remove a breakpoint, all of them without argument
+watch <loc>           stop when a register or memory word changes
+print [loc]           show a register, the pc or a memory word, all registers without argument
+x/<n> <addr>          dump n memory words from addr
+changes               list the memory words changed since the start
+disas [where]         list the instructions around the pc or the given instruction
+backtrace             list the subroutines being executed, as guessed from jalr
+set <loc> = <value>   change a register, the pc or a memory word
+restart               run the program again from the start
+quit
+
+Instructions (where) are given by label, by source line as <line> or <file>:<line>,
+or by ROM index as *<index>. A source line without code stands for the next one.
+Locations are r0 to r7 (or sp, ra), pc and mem[addr]. Numbers may be written in
+decimal, 0x hexadecimal or 0b binary, labels are accepted for addresses and values.
+An empty line repeats the last command.";
+
+/// A register, the pc or a RAM word, as named in `print`, `watch` and `set`.
+#[derive(Clone, Copy, PartialEq)]
+enum Location {
+    Reg(usize),
+    Pc,
+    Mem(u16),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Reg(reg) => write!(f, "r{}", reg),
+            Location::Pc => write!(f, "pc"),
+            Location::Mem(addr) => write!(f, "mem[{:#06x}]", addr),
+        }
+    }
+}
+
+/// Why execution gave control back to the user.
+enum Stop {
+    Done,
+    Breakpoint,
+    Watch(Location, i16, i16),
+    Halted,
+    Fault,
+}
+
+/// Interactive debugger driving a `Risc16` one instruction at a time.
+pub(crate) struct Debugger {
+    proc: Risc16,
+    rom: Rom,
+    labels: HashMap<String, usize>,
+    listing: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    /// Watched locations with their last known value.
+    watches: Vec<(Location, i16)>,
+    /// False once the program halted or faulted, until `restart`.
+    running: bool,
+    /// Output buffer and console text already shown to the user.
+    buffer_shown: usize,
+    console_shown: usize,
+    last_command: String,
+}
+
+impl Debugger {
+    pub(crate) fn new(mut proc: Risc16, rom: Rom, labels: HashMap<String, usize>) -> Debugger {
+        proc.labels = labels.clone();
+        proc.prepare_run();
+        Debugger {
+            listing: format_code(&rom, &labels),
+            proc,
+            rom,
+            labels,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            running: true,
+            buffer_shown: 0,
+            console_shown: 0,
+            last_command: String::new(),
+        }
+    }
+
+    /// Read commands from `input` until `quit` or the end of the input.
+    pub(crate) fn repl(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.current())?;
+        write!(out, "(risc16) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.command(&line?) {
+                Ok(Some(text)) => write!(out, "{}", text)?,
+                Ok(None) => return Ok(()),
+                Err(msg) => writeln!(out, "{}", msg)?,
+            }
+            write!(out, "(risc16) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    /// Run one command line, returns the text to show or None to quit.
+    fn command(&mut self, line: &str) -> Result<Option<String>, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let cmd = parts.next().unwrap_or("");
+        let arg = parts.next().unwrap_or("").trim();
+        let text = match cmd {
+            "" => String::new(),
+            "step" | "s" | "stepi" | "si" => {
+                let steps = match arg {
+                    "" => 1,
+                    n => n
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| format!("Bad step count: {}", n))?,
+                };
+                self.resume(Some(steps), None)?
+            }
+            "next" | "n" => self.next()?,
+            "continue" | "c" => self.resume(None, None)?,
+            "break" | "b" => self.set_breakpoint(arg)?,
+            "delete" | "d" => self.delete_breakpoint(arg)?,
+            "watch" | "w" => self.watch(arg)?,
+            "print" | "p" => self.print(arg)?,
+            "disas" => self.disas(arg)?,
+            "backtrace" | "bt" => self.proc.format_backtrace(),
+            "changes" => self.proc.format_memory_diff(),
+            "set" => self.set(arg)?,
+            "restart" => self.restart(),
+            "help" | "h" => format!("{}\n", HELP),
+            "quit" | "q" => return Ok(None),
+            cmd if cmd == "x" || cmd.starts_with("x/") => self.examine(&cmd[1..], arg)?,
+            cmd => return Err(format!("Unknown command: {}, try help", cmd)),
+        };
+        Ok(Some(text))
+    }
+
+    /// Execute instructions until `steps` are done, the pc reaches `until`,
+    /// or a breakpoint, a watchpoint, HALT or a fault stops the program.
+    fn resume(&mut self, steps: Option<u32>, until: Option<usize>) -> Result<String, String> {
+        if !self.running {
+            return Err("The program is not running, use restart".to_string());
+        }
+        let mut count = 0;
+        let stop = loop {
+            match self.proc.step(&self.rom) {
+                Ok(true) => (),
+                Ok(false) => break Stop::Halted,
+                Err(e) => {
+                    self.proc.fail(e);
+                    break Stop::Fault;
+                }
+            }
+            count += 1;
+            if let Some(stop) = self.check_watches() {
+                break stop;
+            } else if Some(self.proc.pc) == until {
+                break Stop::Done;
+            } else if self.breakpoints.contains(&self.proc.pc) {
+                break Stop::Breakpoint;
+            } else if Some(count) == steps {
+                break Stop::Done;
+            }
+        };
+        Ok(self.report(stop))
+    }
+
+    /// Step over `jalr` calls by running until the instruction after the call.
+    fn next(&mut self) -> Result<String, String> {
+        match self.rom.get(self.proc.pc) {
+            Some((instr, Args::A23(args))) if instr == "jalr" && args[0] != 0 => {
+                let ret = self.proc.pc + 1;
+                self.resume(None, Some(ret))
+            }
+            _ => self.resume(Some(1), None),
+        }
+    }
+
+    fn check_watches(&mut self) -> Option<Stop> {
+        let mut stop = None;
+        for i in 0..self.watches.len() {
+            let (loc, old) = self.watches[i];
+            let new = self.value(loc).unwrap_or(0);
+            if new != old {
+                self.watches[i].1 = new;
+                stop = stop.or(Some(Stop::Watch(loc, old, new)));
+            }
+        }
+        stop
+    }
+
+    /// New program output, the reason of the stop and the next instruction.
+    fn report(&mut self, stop: Stop) -> String {
+        let mut text = String::new();
+        text.push_str(&self.proc.buffer[self.buffer_shown..]);
+        self.buffer_shown = self.proc.buffer.len();
+        let console = self.proc.console();
+        if console.len() > self.console_shown {
+            writeln!(text, "Console: {}", &console[self.console_shown..]).unwrap();
+            self.console_shown = console.len();
+        }
+        match stop {
+            Stop::Done | Stop::Fault => (),
+            Stop::Breakpoint => {
+                writeln!(text, "Breakpoint at {}", self.proc.pc).unwrap();
+                if !self.proc.call_stack.frames.is_empty() {
+                    text.push_str(&self.proc.format_backtrace());
+                }
+            }
+            Stop::Watch(loc, old, new) => {
+                writeln!(text, "Watchpoint {}: {} -> {}", loc, show(old), show(new)).unwrap()
+            }
+            Stop::Halted => writeln!(
+                text,
+                "Program halted after {} instructions",
+                self.proc.instr_count
+            )
+            .unwrap(),
+        }
+        if matches!(stop, Stop::Halted | Stop::Fault) {
+            self.running = false;
+        }
+        writeln!(text, "{}", self.current()).unwrap();
+        text
+    }
+
+    fn set_breakpoint(&mut self, arg: &str) -> Result<String, String> {
+        if arg.is_empty() {
+            if self.breakpoints.is_empty() {
+                return Ok("No breakpoints\n".to_string());
+            }
+            return Ok(self
+                .breakpoints
+                .iter()
+                .map(|i| self.breakpoint(*i))
+                .collect());
+        }
+        let index = self.instruction(arg)?;
+        self.breakpoints.insert(index);
+        Ok(self.breakpoint(index))
+    }
+
+    fn breakpoint(&self, index: usize) -> String {
+        match self.proc.source_map.location(index) {
+            Some(location) => format!(
+                "Breakpoint at {} ({})\n",
+                self.line(index).trim_start(),
+                location
+            ),
+            None => format!("Breakpoint at {}\n", self.line(index).trim_start()),
+        }
+    }
+
+    fn delete_breakpoint(&mut self, arg: &str) -> Result<String, String> {
+        if arg.is_empty() {
+            self.breakpoints.clear();
+            return Ok(String::new());
+        }
+        let index = self.instruction(arg)?;
+        if !self.breakpoints.remove(&index) {
+            return Err(format!("No breakpoint at instruction {}", index));
+        }
+        Ok(String::new())
+    }
+
+    fn watch(&mut self, arg: &str) -> Result<String, String> {
+        let loc = self.location(arg)?;
+        let val = self
+            .value(loc)
+            .ok_or_else(|| format!("Cannot watch {}, outside RAM", loc))?;
+        if !self.watches.iter().any(|(watched, _)| *watched == loc) {
+            self.watches.push((loc, val));
+        }
+        Ok(format!("Watching {} = {}\n", loc, show(val)))
+    }
+
+    fn print(&mut self, arg: &str) -> Result<String, String> {
+        if arg.is_empty() {
+            let mut text = String::new();
+            for (i, val) in self.proc.registers.iter().enumerate() {
+                writeln!(text, "r{} = {}", i, show(*val)).unwrap();
+            }
+            writeln!(
+                text,
+                "pc = {}, instr. count = {}",
+                self.proc.pc, self.proc.instr_count
+            )
+            .unwrap();
+            return Ok(text);
+        }
+        let loc = self.location(arg)?;
+        match self.value(loc) {
+            Some(val) => Ok(format!("{} = {}\n", loc, show(val))),
+            None => Err(format!("Cannot print {}, outside RAM", loc)),
+        }
+    }
+
+    /// `x/<count> <addr>`, `fmt` is what follows the x.
+    fn examine(&mut self, fmt: &str, arg: &str) -> Result<String, String> {
+        let count: u16 = match fmt.strip_prefix('/') {
+            None | Some("") => 1,
+            Some(n) => n.parse().map_err(|_| format!("Bad word count: {}", n))?,
+        };
+        let start = self.address(arg)?;
+        let formats = [NumberFormat::Hex, NumberFormat::Signed];
+        Ok(self.proc.dump_memory(start, count, &formats))
+    }
+
+    fn disas(&mut self, arg: &str) -> Result<String, String> {
+        if self.rom.is_empty() {
+            return Err("The program has no instructions".to_string());
+        }
+        let center = match arg {
+            "" => self.proc.pc,
+            arg => self.instruction(arg)?,
+        };
+        let first = center.saturating_sub(DISAS_CONTEXT);
+        let last = (center + DISAS_CONTEXT).min(self.rom.len().saturating_sub(1));
+        Ok((first..=last)
+            .map(|i| {
+                let marker = if i == self.proc.pc { "=>" } else { "  " };
+                let bp = if self.breakpoints.contains(&i) {
+                    "*"
+                } else {
+                    " "
+                };
+                format!("{}{} {}\n", marker, bp, self.line(i))
+            })
+            .collect())
+    }
+
+    fn set(&mut self, arg: &str) -> Result<String, String> {
+        let mut parts = if arg.contains('=') {
+            arg.splitn(2, '=')
+        } else {
+            arg.splitn(2, ' ')
+        };
+        let loc = self.location(parts.next().unwrap_or("").trim())?;
+        let val_str = parts.next().unwrap_or("").trim();
+        let val = resolve_imm(val_str, &self.labels)
+            .ok_or_else(|| format!("Bad value: {}", val_str))? as i16;
+        match loc {
+            Location::Reg(0) => return Err("r0 is hardwired to 0".to_string()),
+            Location::Reg(reg) => self.proc.write_reg(reg, val).map_err(|e| e.to_string())?,
+            Location::Pc => self.proc.pc = val as u16 as usize,
+            Location::Mem(addr) => {
+                let word = self
+                    .proc
+                    .ram
+                    .get_mut(addr as usize)
+                    .ok_or_else(|| format!("Cannot set {}, outside RAM", loc))?;
+                *word = val;
+                self.proc.ram_written[addr as usize] = true;
+            }
+        }
+        // a change made by hand does not trigger the watchpoints
+        for i in 0..self.watches.len() {
+            self.watches[i].1 = self.value(self.watches[i].0).unwrap_or(0);
+        }
+        Ok(format!("{} = {}\n", loc, show(val)))
+    }
+
+    fn restart(&mut self) -> String {
+        self.proc.reset_state();
+        self.proc.devices = devices::default_devices(&[]);
+        self.proc.labels = self.labels.clone();
+        self.proc.prepare_run();
+        for i in 0..self.watches.len() {
+            self.watches[i].1 = self.value(self.watches[i].0).unwrap_or(0);
+        }
+        self.running = true;
+        self.buffer_shown = 0;
+        self.console_shown = 0;
+        format!("{}\n", self.current())
+    }
+
+    /// The instruction about to be executed.
+    fn current(&self) -> String {
+        if self.proc.pc < self.rom.len() {
+            format!("=> {}", self.line(self.proc.pc))
+        } else {
+            format!("=> {} (outside ROM)", self.proc.pc)
+        }
+    }
+
+    fn line(&self, index: usize) -> String {
+        format!("{:>4}  {}", index, self.listing[index])
+    }
+
+    /// ROM index of a label, a source line or `*<index>`.
+    fn instruction(&self, arg: &str) -> Result<usize, String> {
+        let index = if let Some(index) = self.labels.get(arg) {
+            *index
+        } else if let Some(index) = arg.strip_prefix('*') {
+            index
+                .trim()
+                .parse()
+                .map_err(|_| format!("Bad instruction index: {}", index))?
+        } else {
+            self.source_line(arg)?
+        };
+        if index >= self.rom.len() {
+            return Err(format!(
+                "No instruction {}, the program has {}",
+                index,
+                self.rom.len()
+            ));
+        }
+        Ok(index)
+    }
+
+    /// First instruction at or after a source line given as `<line>` or `<file>:<line>`.
+    fn source_line(&self, arg: &str) -> Result<usize, String> {
+        let source_map = &self.proc.source_map;
+        let line = match arg.rsplit_once(':') {
+            Some((file, line)) => {
+                let same_file = source_map
+                    .file
+                    .as_deref()
+                    .is_some_and(|f| Path::new(f).ends_with(Path::new(file)));
+                if !same_file {
+                    return Err(format!("No source file {}", file));
+                }
+                line
+            }
+            None => arg,
+        };
+        let line: usize = line
+            .trim()
+            .parse()
+            .map_err(|_| format!("Unknown label or source line: {}", arg))?;
+        source_map
+            .lines
+            .iter()
+            .position(|l| *l >= line)
+            .ok_or_else(|| format!("No instruction at or after line {}", line))
+    }
+
+    fn address(&self, arg: &str) -> Result<u16, String> {
+        resolve_imm(arg, &self.labels)
+            .map(|addr| addr as u16)
+            .ok_or_else(|| format!("Bad address: {}", arg))
+    }
+
+    fn location(&self, arg: &str) -> Result<Location, String> {
+        if arg == "pc" {
+            Ok(Location::Pc)
+        } else if let Some(addr) = arg.strip_prefix("mem[").and_then(|a| a.strip_suffix(']')) {
+            Ok(Location::Mem(self.address(addr)?))
+        } else {
+            process_register(arg)
+                .map(Location::Reg)
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    /// Current value of `loc`, None for memory outside RAM so that devices are not touched.
+    fn value(&self, loc: Location) -> Option<i16> {
+        match loc {
+            Location::Reg(reg) => self.proc.registers.get(reg).copied(),
+            Location::Pc => Some(self.proc.pc as i16),
+            Location::Mem(addr) => self.proc.ram.get(addr as usize).copied(),
+        }
+    }
+}
+
+/// A word in hexadecimal and signed decimal.
+pub(crate) fn show(val: i16) -> String {
+    format!("{:#06x} ({})", val, val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Archtype};
+
+    fn debugger(code: &str) -> Debugger {
+        let (rom, labels, source_map) = assemble(code, false, Archtype::IS0).unwrap();
+        let mut proc = Risc16::new(Archtype::IS0, 1000, false);
+        proc.source_map = source_map;
+        Debugger::new(proc, rom, labels)
+    }
+
+    fn run(debugger: &mut Debugger, line: &str) -> Result<String, String> {
+        debugger.command(line).map(Option::unwrap)
+    }
+
+    #[test]
+    fn disas_empty_program() {
+        let mut debugger = debugger("// nothing to run\n");
+        assert!(run(&mut debugger, "disas").is_err());
+        assert!(run(&mut debugger, "break 1").is_err());
+    }
+
+    #[test]
+    fn step_zero_is_rejected() {
+        let mut debugger = debugger("addi 1,1,1\naddi 1,1,1\nhalt");
+        assert_eq!(
+            run(&mut debugger, "step 0").unwrap_err(),
+            "Bad step count: 0"
+        );
+        assert_eq!(debugger.proc.instr_count, 0);
+        run(&mut debugger, "step 2").unwrap();
+        assert_eq!(debugger.proc.registers[1], 2);
+    }
+
+    #[test]
+    fn breakpoints_on_source_lines() {
+        let code = "// count to 3\nmovi 1,3\n\nloop: addi 1,1,-1\n  beq 1,0,done\n  beq 0,0,loop\ndone: halt";
+        let mut debugger = debugger(code);
+        // line 3 is empty, the breakpoint goes to the instruction of line 4
+        let text = run(&mut debugger, "break 3").unwrap();
+        assert!(text.contains("loop: addi 1,1,-1 (line 4)"), "{}", text);
+        assert!(debugger.breakpoints.contains(&1));
+        run(&mut debugger, "break *3").unwrap();
+        assert!(debugger.breakpoints.contains(&3));
+        run(&mut debugger, "delete *3").unwrap();
+        assert!(run(&mut debugger, "break 8").is_err());
+        assert!(run(&mut debugger, "break other.txt:4").is_err());
+
+        run(&mut debugger, "continue").unwrap();
+        assert_eq!(debugger.proc.pc, 1);
+        run(&mut debugger, "continue").unwrap();
+        assert_eq!((debugger.proc.pc, debugger.proc.registers[1]), (1, 2));
+    }
+}
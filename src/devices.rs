@@ -0,0 +1,370 @@
+use crate::{CustomError, FaultKind, RiscResult};
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+/// Default base addresses of the built-in devices, at the top of the address space.
+pub(crate) const CONSOLE_ADDR: u16 = 0xfff0;
+pub(crate) const INPUT_ADDR: u16 = 0xfff1;
+pub(crate) const TIMER_ADDR: u16 = 0xfff2;
+
+/// A peripheral mapped on a range of memory addresses.
+///
+/// `lw` and `sw` on an address inside `range` are forwarded to the device instead of RAM.
+pub(crate) trait Device: Send {
+    fn name(&self) -> &str;
+
+    fn range(&self) -> RangeInclusive<u16>;
+
+    fn read(&mut self, addr: u16, instr_count: u32) -> RiscResult<i16>;
+
+    fn write(&mut self, addr: u16, val: i16, instr_count: u32) -> RiscResult<()>;
+
+    /// Text produced by the device during the run, if any.
+    fn output(&self) -> Option<&str> {
+        None
+    }
+
+    /// Human readable state of the device, for display after a run.
+    fn state(&self) -> Option<String> {
+        None
+    }
+
+    /// Put the device back in the state it had when mapped, before a new run.
+    fn reset(&mut self) -> RiscResult<()> {
+        Ok(())
+    }
+}
+
+/// Writes append the low byte as a character to the output, reads return 0.
+pub(crate) struct Console {
+    addr: u16,
+    output: String,
+}
+
+impl Console {
+    pub(crate) fn new(addr: u16) -> Console {
+        Console {
+            addr,
+            output: String::new(),
+        }
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.addr..=self.addr
+    }
+
+    fn read(&mut self, _addr: u16, _instr_count: u32) -> RiscResult<i16> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _addr: u16, val: i16, _instr_count: u32) -> RiscResult<()> {
+        self.output.push(val as u8 as char);
+        Ok(())
+    }
+
+    fn output(&self) -> Option<&str> {
+        Some(&self.output)
+    }
+
+    fn reset(&mut self) -> RiscResult<()> {
+        self.output.clear();
+        Ok(())
+    }
+}
+
+/// Reads consume the next value of the input queue, or -1 once it is empty.
+pub(crate) struct Input {
+    addr: u16,
+    queue: VecDeque<i16>,
+    /// The queue as given, refilled on reset.
+    initial: Vec<i16>,
+}
+
+impl Input {
+    pub(crate) fn new(addr: u16, queue: &[i16]) -> Input {
+        Input {
+            addr,
+            queue: queue.iter().copied().collect(),
+            initial: queue.to_vec(),
+        }
+    }
+}
+
+impl Device for Input {
+    fn name(&self) -> &str {
+        "input"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.addr..=self.addr
+    }
+
+    fn read(&mut self, _addr: u16, _instr_count: u32) -> RiscResult<i16> {
+        Ok(self.queue.pop_front().unwrap_or(-1))
+    }
+
+    fn write(&mut self, _addr: u16, _val: i16, _instr_count: u32) -> RiscResult<()> {
+        Ok(())
+    }
+
+    fn state(&self) -> Option<String> {
+        Some(format!("{} value(s) left", self.queue.len()))
+    }
+
+    fn reset(&mut self) -> RiscResult<()> {
+        self.queue = self.initial.iter().copied().collect();
+        Ok(())
+    }
+}
+
+/// Reads return the number of instructions executed since the last write,
+/// writing a value restarts the counter from it.
+pub(crate) struct Timer {
+    addr: u16,
+    start: u32,
+}
+
+impl Timer {
+    pub(crate) fn new(addr: u16) -> Timer {
+        Timer { addr, start: 0 }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.addr..=self.addr
+    }
+
+    fn read(&mut self, _addr: u16, instr_count: u32) -> RiscResult<i16> {
+        Ok(instr_count.wrapping_sub(self.start) as i16)
+    }
+
+    fn write(&mut self, _addr: u16, val: i16, instr_count: u32) -> RiscResult<()> {
+        self.start = instr_count.wrapping_sub(val as u16 as u32);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> RiscResult<()> {
+        self.start = 0;
+        Ok(())
+    }
+}
+
+/// A device implemented by a Python object.
+///
+/// The object must provide `read(addr) -> int` and `write(addr, value)`,
+/// and may provide `state() -> str` to be rendered after a run and `reset()`,
+/// called before every run.
+pub(crate) struct PyDevice {
+    name: String,
+    first: u16,
+    last: u16,
+    obj: PyObject,
+}
+
+impl PyDevice {
+    pub(crate) fn new(name: String, first: u16, last: u16, obj: PyObject) -> PyDevice {
+        PyDevice {
+            name,
+            first,
+            last,
+            obj,
+        }
+    }
+
+    fn fault(&self, err: PyErr) -> CustomError {
+        CustomError::fault(
+            FaultKind::DeviceError,
+            &format!("Device {} failed: {}", self.name, err),
+        )
+    }
+}
+
+impl Device for PyDevice {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.first..=self.last
+    }
+
+    fn read(&mut self, addr: u16, _instr_count: u32) -> RiscResult<i16> {
+        Python::with_gil(|py| {
+            self.obj
+                .call_method1(py, "read", (addr,))
+                .and_then(|val| val.extract::<i32>(py))
+                .map(|val| val as i16)
+                .map_err(|e| self.fault(e))
+        })
+    }
+
+    fn write(&mut self, addr: u16, val: i16, _instr_count: u32) -> RiscResult<()> {
+        Python::with_gil(|py| {
+            self.obj
+                .call_method1(py, "write", (addr, val))
+                .map(|_| ())
+                .map_err(|e| self.fault(e))
+        })
+    }
+
+    fn state(&self) -> Option<String> {
+        Python::with_gil(|py| {
+            self.obj
+                .call_method0(py, "state")
+                .and_then(|state| state.extract::<String>(py))
+                .ok()
+        })
+    }
+
+    fn reset(&mut self) -> RiscResult<()> {
+        Python::with_gil(|py| {
+            if !self
+                .obj
+                .as_ref(py)
+                .hasattr("reset")
+                .map_err(|e| self.fault(e))?
+            {
+                return Ok(());
+            }
+            self.obj
+                .call_method0(py, "reset")
+                .map(|_| ())
+                .map_err(|e| self.fault(e))
+        })
+    }
+}
+
+/// Build a built-in device from its name, as given in the Python `devices` option.
+pub(crate) fn from_config(name: &str, addr: u16, input: &[i16]) -> RiscResult<Box<dyn Device>> {
+    match name {
+        "console" => Ok(Box::new(Console::new(addr))),
+        "input" => Ok(Box::new(Input::new(addr, input))),
+        "timer" => Ok(Box::new(Timer::new(addr))),
+        _ => Err(format!("Unknown device: {}", name).into()),
+    }
+}
+
+/// The devices mapped when none are configured.
+pub(crate) fn default_devices(input: &[i16]) -> Vec<Box<dyn Device>> {
+    vec![
+        Box::new(Console::new(CONSOLE_ADDR)),
+        Box::new(Input::new(INPUT_ADDR, input)),
+        Box::new(Timer::new(TIMER_ADDR)),
+    ]
+}
+
+/// Build the devices of one run, the default ones if `config` is None.
+pub(crate) fn build_devices(
+    config: &Option<Vec<(String, u16)>>,
+    input: &[i16],
+) -> RiscResult<Vec<Box<dyn Device>>> {
+    match config {
+        Some(config) => config
+            .iter()
+            .map(|(name, addr)| from_config(name, *addr, input))
+            .collect(),
+        None => Ok(default_devices(input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    const PY_DEVICE: &str = "
+class Device:
+    def __init__(self):
+        self.words = {}
+        self.resets = 0
+    def read(self, addr):
+        if addr == 0x11:
+            raise ValueError('boom')
+        return self.words.get(addr, 0) + 1
+    def write(self, addr, value):
+        self.words[addr] = value
+    def state(self):
+        return '%d word(s)' % len(self.words)
+    def reset(self):
+        self.resets += 1
+        self.words = {}
+";
+
+    /// A PyDevice on 0x10-0x11 for an instance of the class `Device` defined by `code`.
+    fn py_device(py: Python, code: &str) -> (PyDevice, PyObject) {
+        let locals = PyDict::new(py);
+        py.run(code, None, Some(locals)).unwrap();
+        let obj: PyObject = py.eval("Device()", None, Some(locals)).unwrap().into();
+        let device = PyDevice::new("py".to_string(), 0x10, 0x11, obj.clone_ref(py));
+        (device, obj)
+    }
+
+    fn fault_message(err: CustomError) -> String {
+        match err {
+            CustomError::Fault(fault) => {
+                assert_eq!(fault.kind, FaultKind::DeviceError);
+                fault.message
+            }
+            err => panic!("expected a fault, got: {}", err),
+        }
+    }
+
+    #[test]
+    fn py_device_callbacks() {
+        Python::with_gil(|py| {
+            let (mut device, obj) = py_device(py, PY_DEVICE);
+            assert_eq!(device.range(), 0x10..=0x11);
+            device.write(0x10, -5, 0).unwrap();
+            assert_eq!(device.read(0x10, 0).unwrap(), -4);
+            assert_eq!(device.state().as_deref(), Some("1 word(s)"));
+            device.reset().unwrap();
+            assert_eq!(device.read(0x10, 0).unwrap(), 1);
+            let resets: u32 = obj.getattr(py, "resets").unwrap().extract(py).unwrap();
+            assert_eq!(resets, 1);
+        });
+    }
+
+    #[test]
+    fn py_device_errors() {
+        Python::with_gil(|py| {
+            let (mut device, _) = py_device(py, PY_DEVICE);
+            let message = fault_message(device.read(0x11, 0).unwrap_err());
+            assert_eq!(message, "Device py failed: ValueError: boom");
+            // read must return an int, write and state may be missing
+            let code = "class Device:\n    def read(self, addr):\n        return 'x'\n";
+            let (mut device, _) = py_device(py, code);
+            assert!(
+                fault_message(device.read(0x10, 0).unwrap_err()).starts_with("Device py failed")
+            );
+            assert!(device.write(0x10, 1, 0).is_err());
+            assert_eq!(device.state(), None);
+            assert!(device.reset().is_ok());
+        });
+    }
+
+    #[test]
+    fn builtin_devices_reset() {
+        let mut devices = default_devices(&[7]);
+        devices[0].write(CONSOLE_ADDR, 72, 0).unwrap();
+        assert_eq!(devices[1].read(INPUT_ADDR, 0).unwrap(), 7);
+        devices[2].write(TIMER_ADDR, 0, 10).unwrap();
+        for device in &mut devices {
+            device.reset().unwrap();
+        }
+        assert_eq!(devices[0].output(), Some(""));
+        assert_eq!(devices[1].read(INPUT_ADDR, 0).unwrap(), 7);
+        assert_eq!(devices[2].read(TIMER_ADDR, 12).unwrap(), 12);
+    }
+}
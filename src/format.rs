@@ -0,0 +1,167 @@
+use crate::syntax::{self, SyntaxTree};
+use crate::{load_rom, RiscResult};
+
+/// Mnemonics start at least at this column, so that short labels fit before them.
+const MIN_INDENT: usize = 8;
+
+/// Parts of a source line, as written.
+struct Line<'a> {
+    /// The labels with their colons, like `loop:` or `a: b:`.
+    label: Option<String>,
+    mnemonic: Option<&'a str>,
+    operands: Vec<&'a str>,
+    /// The comment with its leading `//`.
+    comment: Option<&'a str>,
+    /// The line starts at the first column, such comments are left there.
+    flush: bool,
+}
+
+fn split_line<'a>(tree: &SyntaxTree<'a>, line: &syntax::Line) -> Line<'a> {
+    let labels: Vec<String> = line
+        .labels
+        .iter()
+        .map(|label| format!("{}:", tree.text(label)))
+        .collect();
+    let statement = line.statement.as_ref();
+    Line {
+        label: Some(labels.join(" ")).filter(|label| !label.is_empty()),
+        mnemonic: statement.map(|s| tree.text(&s.name)),
+        operands: statement
+            .map(|s| s.operands.iter().map(|op| tree.text(op)).collect())
+            .unwrap_or_default(),
+        comment: line.comment.as_ref().map(|c| tree.text(c).trim_end()),
+        flush: !tree.text(&line.span).starts_with(char::is_whitespace),
+    }
+}
+
+/// Format assembly source: labels, mnemonics, operands and trailing comments each get
+/// their own column, comments and blank lines are kept. Formatting twice changes nothing.
+///
+/// Code that does not assemble is rejected rather than reshaped.
+pub(crate) fn format_source(code: &str) -> RiscResult<String> {
+    load_rom(code.to_string())?;
+    let tree = SyntaxTree::parse(code);
+    let lines: Vec<Line> = tree.lines.iter().map(|l| split_line(&tree, l)).collect();
+    let indent = lines
+        .iter()
+        .filter(|line| line.mnemonic.is_some())
+        .filter_map(|line| line.label.as_ref())
+        .map(|label| label.chars().count() + 1)
+        .fold(MIN_INDENT, usize::max);
+    let mnemonic_width = lines
+        .iter()
+        .filter_map(|line| line.mnemonic)
+        .map(|mnemonic| mnemonic.chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+
+    let codes: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            let label = line.label.clone();
+            let text = match line.mnemonic {
+                Some(mnemonic) => format!(
+                    "{:<indent$}{:<width$}{}",
+                    label.unwrap_or_default(),
+                    mnemonic,
+                    line.operands.join(","),
+                    indent = indent,
+                    width = mnemonic_width,
+                ),
+                None => label.unwrap_or_default(),
+            };
+            text.trim_end().to_string()
+        })
+        .collect();
+    // trailing comments are aligned two spaces after the longest line that has one
+    let comment_column = lines
+        .iter()
+        .zip(&codes)
+        .filter(|(line, code)| line.comment.is_some() && !code.is_empty())
+        .map(|(_, code)| code.chars().count() + 2)
+        .max()
+        .unwrap_or(0);
+
+    let mut formatted = String::new();
+    for (line, code) in lines.iter().zip(&codes) {
+        let text = match line.comment {
+            Some(comment) if !code.is_empty() => {
+                format!("{:<column$}{}", code, comment, column = comment_column)
+            }
+            Some(comment) if line.flush => comment.to_string(),
+            Some(comment) => format!("{:indent$}{}", "", comment, indent = indent),
+            None => code.clone(),
+        };
+        formatted.push_str(&text);
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// Labels, operands and comments written every which way.
+    const MESSY: &str = "start:addi 1 , 2,-3 // x\n   // alone\n\nlong_label: lw 1, 4 (sp)\n\
+                         // flush\n   beq 1,0,start+1 // back\nhalt";
+
+    /// Sources of the programs in tests/.
+    fn fixtures() -> Vec<(String, String)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let mut sources: Vec<(String, String)> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .map(|path| {
+                let source = std::fs::read_to_string(&path).unwrap();
+                (path.display().to_string(), source)
+            })
+            .collect();
+        sources.sort();
+        assert!(!sources.is_empty());
+        sources
+    }
+
+    #[test]
+    fn syntax_tree_is_lossless() {
+        for (path, source) in fixtures() {
+            assert_eq!(SyntaxTree::parse(&source).to_source(), source, "{}", path);
+        }
+        let messy = "a:b:  addi 1 , 2,-3//x\n\t\n  // c\r\nlw 1,4(sp)";
+        assert_eq!(SyntaxTree::parse(messy).to_source(), messy);
+    }
+
+    #[test]
+    fn format_messy() {
+        // labels, mnemonics, operands and trailing comments each in their column,
+        // comments on their own line and blank lines kept, whitespace inside operands too
+        let expected = "\
+start:      addi 1,2,-3       // x
+            // alone
+
+long_label: lw   1,4 (sp)
+// flush
+            beq  1,0,start+1  // back
+            halt
+";
+        assert_eq!(format_source(MESSY).unwrap(), expected);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let mut sources = fixtures();
+        sources.push(("messy".to_string(), MESSY.to_string()));
+        for (path, source) in sources {
+            let formatted = format_source(&source).unwrap();
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", path);
+            // only the layout changes
+            let rom = |code: &str| {
+                let (rom, labels) = load_rom(code.to_string()).unwrap();
+                (format!("{:?}", rom), labels)
+            };
+            assert_eq!(rom(&formatted), rom(&source), "{}", path);
+        }
+    }
+}
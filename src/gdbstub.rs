@@ -0,0 +1,443 @@
+use crate::{FaultKind, Risc16, Rom};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+
+/// Registers r0 to r7 then the pc, 16 bits each. The pc is the ROM index of the next
+/// instruction, memory addresses are byte addresses in RAM (word `a` is at `2 * a`).
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.risc16.core">
+    <reg name="r0" bitsize="16" type="int" regnum="0"/>
+    <reg name="r1" bitsize="16" type="int"/>
+    <reg name="r2" bitsize="16" type="int"/>
+    <reg name="r3" bitsize="16" type="int"/>
+    <reg name="r4" bitsize="16" type="int"/>
+    <reg name="r5" bitsize="16" type="int"/>
+    <reg name="r6" bitsize="16" type="data_ptr"/>
+    <reg name="r7" bitsize="16" type="code_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const PC_REGNUM: usize = 8;
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGSEGV: u8 = 11;
+const SIGXCPU: u8 = 24;
+
+/// Server side of the GDB remote serial protocol, wrapping a `Risc16`.
+pub(crate) struct GdbStub {
+    proc: Risc16,
+    rom: Rom,
+    breakpoints: BTreeSet<usize>,
+    no_ack: bool,
+    /// Stop reply once the program halted or faulted, repeated on every resume.
+    finished: Option<String>,
+}
+
+impl GdbStub {
+    pub(crate) fn new(mut proc: Risc16, rom: Rom, labels: HashMap<String, usize>) -> GdbStub {
+        proc.labels = labels;
+        proc.prepare_run();
+        GdbStub {
+            proc,
+            rom,
+            breakpoints: BTreeSet::new(),
+            no_ack: false,
+            finished: None,
+        }
+    }
+
+    /// Answer packets until the client kills or detaches, or closes the connection.
+    pub(crate) fn serve(
+        &mut self,
+        mut input: impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        while let Some(packet) = read_packet(&mut input)? {
+            let packet = match packet {
+                Ok(packet) => packet,
+                Err(()) => {
+                    out.write_all(b"-")?;
+                    out.flush()?;
+                    continue;
+                }
+            };
+            if !self.no_ack {
+                out.write_all(b"+")?;
+            }
+            if packet == "k" {
+                return Ok(());
+            } else if packet.starts_with('D') {
+                return send_packet(out, "OK");
+            }
+            send_packet(out, &self.handle(&packet))?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match cmd {
+            "?" => self.stop_reply(SIGTRAP),
+            "g" => (0..=PC_REGNUM)
+                .map(|i| encode_word(self.register(i)))
+                .collect(),
+            "G" => {
+                // decoded in full first, a bad packet leaves every register as it was
+                let values: Option<Vec<i16>> = (0..=PC_REGNUM)
+                    .map(|i| args.get(4 * i..4 * i + 4).and_then(decode_word))
+                    .collect();
+                match values {
+                    Some(values) if args.len() == 4 * (PC_REGNUM + 1) => {
+                        for (i, val) in values.into_iter().enumerate() {
+                            self.set_register(i, val);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i <= PC_REGNUM => encode_word(self.register(i)),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let reg = parts.next().and_then(|r| usize::from_str_radix(r, 16).ok());
+                match (reg, parts.next().and_then(decode_word)) {
+                    (Some(i), Some(val)) if i <= PC_REGNUM => {
+                        self.set_register(i, val);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => self.write_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.proc.pc = addr;
+                }
+                self.resume(cmd == "s")
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "H" => "OK".to_string(),
+            _ => self.query(packet),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let mut parts = range.splitn(2, ',');
+            let offset = parts.next().and_then(parse_hex).unwrap_or(0);
+            let length = parts.next().and_then(parse_hex).unwrap_or(0);
+            match TARGET_XML.get(offset..) {
+                Some(rest) if rest.len() > length => format!("m{}", &rest[..length]),
+                Some(rest) => format!("l{}", rest),
+                None => "E01".to_string(),
+            }
+        } else {
+            match packet {
+                "QStartNoAckMode" => "OK".to_string(),
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                "qSymbol::" => "OK".to_string(),
+                // anything else is unsupported, the empty reply tells the client so
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn register(&self, i: usize) -> i16 {
+        match i {
+            PC_REGNUM => self.proc.pc as i16,
+            i => self.proc.registers[i],
+        }
+    }
+
+    fn set_register(&mut self, i: usize, val: i16) {
+        match i {
+            PC_REGNUM => self.proc.pc = val as u16 as usize,
+            0 => (),
+            i => {
+                // written by the debugger, so no longer uninitialized
+                self.proc.write_reg(i, val).unwrap();
+            }
+        }
+    }
+
+    /// `m addr,length`: RAM bytes, little endian within each word.
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, length) = parse_range(args)?;
+        let mut data = String::new();
+        for byte in addr..addr.checked_add(length)? {
+            let word = *self.proc.ram.get(byte / 2)? as u16;
+            data.push_str(&format!("{:02x}", (word >> (8 * (byte % 2))) & 0xff));
+        }
+        Some(data)
+    }
+
+    /// `M addr,length:data`
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let mut parts = args.splitn(2, ':');
+        let (addr, length) = parse_range(parts.next()?)?;
+        let data = parts.next()?;
+        // lengths come from the client, they must not overflow
+        if data.len() != length.checked_mul(2)?
+            || 2 * self.proc.ram.len() < addr.checked_add(length)?
+        {
+            return None;
+        }
+        // decoded in full first, like the registers of `G`
+        let bytes = (0..length)
+            .map(|i| u16::from_str_radix(data.get(2 * i..2 * i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        for (i, byte) in bytes.into_iter().enumerate() {
+            let (word, shift) = ((addr + i) / 2, 8 * ((addr + i) % 2));
+            let val = (self.proc.ram[word] as u16 & !(0xff << shift)) | (byte << shift);
+            self.proc.ram[word] = val as i16;
+            self.proc.ram_written[word] = true;
+        }
+        Some("OK".to_string())
+    }
+
+    /// `Z0,addr,kind` or `z0,addr,kind`, software and hardware breakpoints are the same here.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(parse_hex);
+        match (kind, addr) {
+            (Some("0"), Some(addr)) | (Some("1"), Some(addr)) => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                "OK".to_string()
+            }
+            // watchpoints are not supported
+            _ => String::new(),
+        }
+    }
+
+    /// Single step or continue until a breakpoint, HALT or a fault.
+    fn resume(&mut self, single: bool) -> String {
+        if let Some(reply) = &self.finished {
+            return reply.clone();
+        }
+        loop {
+            match self.proc.step(&self.rom) {
+                Ok(true) => (),
+                Ok(false) => {
+                    let reply = "W00".to_string();
+                    self.finished = Some(reply.clone());
+                    return reply;
+                }
+                Err(e) => {
+                    self.proc.fail(e);
+                    let signal = match self.proc.error.as_ref().map(|fault| &fault.kind) {
+                        Some(FaultKind::MemoryOutOfBounds { .. }) | Some(FaultKind::EndOfRom) => {
+                            SIGSEGV
+                        }
+                        Some(FaultKind::IllegalInstruction)
+                        | Some(FaultKind::UnknownInstruction) => SIGILL,
+                        Some(FaultKind::InstrLimit) => SIGXCPU,
+                        _ => SIGABRT,
+                    };
+                    let reply = self.stop_reply(signal);
+                    self.finished = Some(reply.clone());
+                    return reply;
+                }
+            }
+            if self.breakpoints.contains(&self.proc.pc) && !single {
+                return format!("{}swbreak:;", self.stop_reply(SIGTRAP));
+            } else if single {
+                return self.stop_reply(SIGTRAP);
+            }
+        }
+    }
+
+    fn stop_reply(&self, signal: u8) -> String {
+        format!(
+            "T{:02x}{:02x}:{};",
+            signal,
+            PC_REGNUM,
+            encode_word(self.proc.pc as i16)
+        )
+    }
+}
+
+/// Next packet payload, Err(()) on a bad checksum, None at the end of the input.
+/// Acknowledgments and interrupt requests between packets are skipped.
+fn read_packet(input: &mut impl BufRead) -> io::Result<Option<Result<String, ()>>> {
+    let mut skipped = Vec::new();
+    if input.read_until(b'$', &mut skipped)? == 0 || skipped.last() != Some(&b'$') {
+        return Ok(None);
+    }
+    let mut payload = Vec::new();
+    input.read_until(b'#', &mut payload)?;
+    if payload.pop() != Some(b'#') {
+        return Ok(None);
+    }
+    let mut checksum = [0; 2];
+    input.read_exact(&mut checksum)?;
+    let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|c| u8::from_str_radix(c, 16).ok());
+    let sum = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if expected != Some(sum) {
+        return Ok(Some(Err(())));
+    }
+    Ok(Some(Ok(String::from_utf8_lossy(&payload).into_owned())))
+}
+
+fn send_packet(out: &mut impl Write, payload: &str) -> io::Result<()> {
+    let mut escaped = Vec::with_capacity(payload.len());
+    for b in payload.bytes() {
+        if b"#$}*".contains(&b) {
+            escaped.extend_from_slice(&[b'}', b ^ 0x20]);
+        } else {
+            escaped.push(b);
+        }
+    }
+    let sum = escaped.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    out.write_all(b"$")?;
+    out.write_all(&escaped)?;
+    write!(out, "#{:02x}", sum)?;
+    out.flush()
+}
+
+/// Registers go over the wire as little endian bytes.
+fn encode_word(val: i16) -> String {
+    let val = val as u16;
+    format!("{:02x}{:02x}", val & 0xff, val >> 8)
+}
+
+fn decode_word(hex: &str) -> Option<i16> {
+    let low = u16::from_str_radix(hex.get(0..2)?, 16).ok()?;
+    let high = u16::from_str_radix(hex.get(2..4)?, 16).ok()?;
+    Some((low | high << 8) as i16)
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Archtype};
+
+    fn packet(payload: &str) -> String {
+        let sum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${}#{:02x}", payload, sum)
+    }
+
+    /// Replies of the stub to `script`, without the acknowledgments.
+    fn serve(code: &str, script: &str) -> (String, Vec<String>) {
+        let (rom, labels, _) = assemble(code, false, Archtype::IS0).unwrap();
+        let mut stub = GdbStub::new(Risc16::new(Archtype::IS0, 1000, false), rom, labels);
+        let mut out = Vec::new();
+        stub.serve(script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let replies = out
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.rsplit_once('#').unwrap().0.to_string())
+            .collect();
+        (out, replies)
+    }
+
+    #[test]
+    fn scripted_session() {
+        let code = "movi 1,5\naddi 1,1,1\naddi 1,1,1\nhalt";
+        let script: String = [
+            "qSupported:swbreak+",
+            "g",
+            "M0,2:3412",
+            "m0,2",
+            "Z0,2,2",
+            "c",
+            "p1",
+            "s",
+            "z0,2,2",
+            "c",
+            "c",
+            "k",
+        ]
+        .iter()
+        .map(|p| packet(p))
+        .collect();
+        let (out, replies) = serve(code, &script);
+        assert!(out.starts_with('+'));
+        assert_eq!(
+            replies,
+            [
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;swbreak+",
+                &"0000".repeat(9),
+                "OK",
+                "3412",
+                "OK",
+                "T0508:0200;swbreak:;",
+                "0600",
+                "T0508:0300;",
+                "OK",
+                "W00",
+                "W00",
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_packets() {
+        let script = format!(
+            "$g#00{}{}{}{}{}",
+            packet("M1fffffffffffffff,2:0000"),
+            packet("M0,8000000000000000:00"),
+            packet("m0,ffffffffffffffff"),
+            packet("m200,2"),
+            packet("p9"),
+        );
+        let (out, replies) = serve("halt", &script);
+        // the bad checksum is refused, the client sends the packet again
+        assert!(out.starts_with("-+"));
+        assert_eq!(replies, ["E01"; 5]);
+    }
+
+    #[test]
+    fn bad_writes_change_nothing() {
+        let registers = "0100".repeat(9);
+        let script: String = [
+            format!("G{}zz00", &registers[..32]),
+            format!("G{}00", registers),
+            "M0,2:11zz".to_string(),
+            "g".to_string(),
+            "m0,2".to_string(),
+            format!("G{}", registers),
+            "p8".to_string(),
+        ]
+        .iter()
+        .map(|p| packet(p))
+        .collect();
+        let (_, replies) = serve("halt", &script);
+        let zeros = "0000".repeat(9);
+        assert_eq!(replies, ["E01", "E01", "E01", &zeros, "0000", "OK", "0100"]);
+    }
+}
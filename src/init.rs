@@ -0,0 +1,119 @@
+use crate::RiscResult;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// How registers r1 to r7 and the RAM are filled before a run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum InitPolicy {
+    Zero,
+    /// Pseudo random values, reproducible from the seed.
+    Random(u64),
+    /// The same recognizable pattern everywhere.
+    Poison(i16),
+}
+
+pub(crate) const DEFAULT_POISON: i16 = 0x5a5a;
+
+impl InitPolicy {
+    /// Parse "zero", "random", "random:<seed>", "poison" or "poison:<value>".
+    ///
+    /// "random" alone draws a fresh seed, which snapshots keep; give it back as
+    /// "random:<seed>" to start from the same state again.
+    pub(crate) fn from_spec(spec: &str) -> RiscResult<InitPolicy> {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let arg = parts.next().map(str::trim);
+        match (name, arg) {
+            ("zero", None) => Ok(InitPolicy::Zero),
+            ("random", None) => Ok(InitPolicy::Random(random_seed())),
+            ("random", Some(seed)) => Ok(InitPolicy::Random(seed.parse()?)),
+            ("poison", None) => Ok(InitPolicy::Poison(DEFAULT_POISON)),
+            ("poison", Some(val)) => Ok(InitPolicy::Poison(
+                crate::process_string_args(val)
+                    .and_then(crate::fit_word)
+                    .ok_or_else(|| format!("Bad poison value: {}", val))?,
+            )),
+            _ => Err(format!("Unknown init policy: {}", spec).into()),
+        }
+    }
+
+    /// Fill registers r1 to r7 and the RAM, r0 stays 0.
+    pub(crate) fn apply(&self, registers: &mut [i16], ram: &mut [i16]) {
+        let mut rng = SplitMix64(match self {
+            InitPolicy::Random(seed) => *seed,
+            _ => 0,
+        });
+        for word in registers.iter_mut().skip(1).chain(ram.iter_mut()) {
+            *word = match self {
+                InitPolicy::Zero => 0,
+                InitPolicy::Random(_) => rng.next() as i16,
+                InitPolicy::Poison(val) => *val,
+            };
+        }
+    }
+}
+
+/// A seed from the randomly keyed hasher of the standard library, new on every call.
+fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Small generator so that a seed gives the same state on every platform and version.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_specs() {
+        let parse = |spec| InitPolicy::from_spec(spec).unwrap();
+        assert_eq!(parse("zero"), InitPolicy::Zero);
+        assert_eq!(parse("random:42"), InitPolicy::Random(42));
+        assert_eq!(parse("poison"), InitPolicy::Poison(DEFAULT_POISON));
+        assert_eq!(parse(" poison : -2 "), InitPolicy::Poison(-2));
+        assert_eq!(parse("poison:0xffff"), InitPolicy::Poison(-1));
+        // without a seed every parse draws another one
+        assert!(matches!(parse("random"), InitPolicy::Random(_)));
+        assert_ne!(parse("random"), parse("random"));
+
+        let error = |spec| InitPolicy::from_spec(spec).unwrap_err().to_string();
+        assert_eq!(error("poison:0x10000"), "Bad poison value: 0x10000");
+        assert_eq!(error("poison:x"), "Bad poison value: x");
+        assert_eq!(error("ones"), "Unknown init policy: ones");
+        assert_eq!(error("zero:1"), "Unknown init policy: zero:1");
+        assert!(InitPolicy::from_spec("random:-1").is_err());
+    }
+
+    #[test]
+    fn apply_policies() {
+        let state = |policy: InitPolicy| {
+            let (mut registers, mut ram) = ([0; 8], [0; 16]);
+            policy.apply(&mut registers, &mut ram);
+            (registers, ram)
+        };
+        let (registers, ram) = state(InitPolicy::Poison(0x1234));
+        assert_eq!(
+            registers,
+            [0, 0x1234, 0x1234, 0x1234, 0x1234, 0x1234, 0x1234, 0x1234]
+        );
+        assert_eq!(ram, [0x1234; 16]);
+        assert_eq!(state(InitPolicy::Zero), ([0; 8], [0; 16]));
+        // a seed always gives the same state, another seed another one
+        let (registers, ram) = state(InitPolicy::Random(7));
+        assert_eq!(registers[0], 0);
+        assert_eq!(state(InitPolicy::Random(7)), (registers, ram));
+        assert_ne!(state(InitPolicy::Random(8)).1, ram);
+    }
+}
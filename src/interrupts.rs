@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+/// Line raised by the timer, external lines are 1 to 7.
+pub(crate) const TIMER_LINE: u8 = 0;
+/// Reading this address gives the line of the interrupt being handled.
+pub(crate) const CAUSE_ADDR: u16 = 0xfff8;
+
+/// Interrupt controller of the extended architectures.
+///
+/// When a line is pending and no handler is running, the pc is saved and execution
+/// jumps to the handler, `reti` returns to the saved pc. Handlers are not reentrant.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Interrupts {
+    /// Label or ROM index of the handler, interrupts are ignored without one.
+    pub(crate) handler: Option<String>,
+    /// Raise the timer line every `timer_period` instructions.
+    pub(crate) timer_period: Option<u32>,
+    /// External lines to raise, as (instruction count, line).
+    pub(crate) schedule: Vec<(u32, u8)>,
+    pub(crate) pending: u8,
+    pub(crate) saved_pc: Option<usize>,
+    pub(crate) cause: u8,
+}
+
+impl Interrupts {
+    pub(crate) fn raise(&mut self, line: u8) {
+        self.pending |= 1 << (line & 7);
+    }
+
+    /// Raise the lines due at `instr_count`, then return the line to serve if any.
+    pub(crate) fn poll(&mut self, instr_count: u32) -> Option<u8> {
+        if let Some(period) = self.timer_period {
+            if instr_count > 0 && instr_count % period == 0 {
+                self.raise(TIMER_LINE);
+            }
+        }
+        let due: Vec<u8> = self
+            .schedule
+            .iter()
+            .filter(|(at, _)| *at == instr_count)
+            .map(|(_, line)| *line)
+            .collect();
+        for line in due {
+            self.raise(line);
+        }
+        if self.handler.is_none() || self.saved_pc.is_some() || self.pending == 0 {
+            return None;
+        }
+        let line = self.pending.trailing_zeros() as u8;
+        self.pending &= !(1 << line);
+        Some(line)
+    }
+
+    /// Forget what happened during the last run, keeping the configuration.
+    pub(crate) fn reset(&mut self) {
+        self.pending = 0;
+        self.saved_pc = None;
+        self.cause = 0;
+    }
+}
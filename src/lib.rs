@@ -1,13 +1,39 @@
+mod callstack;
+pub mod cli;
+mod convention;
+mod dap;
+mod debugger;
+mod devices;
+mod format;
+mod gdbstub;
+mod init;
+mod interrupts;
+mod lsp;
+mod numbers;
+mod protection;
+mod snapshot;
+mod stack;
+mod syntax;
+
+use callstack::{CallStack, Frame, Mismatch, Transfer};
+use convention::Convention;
+use devices::{Device, PyDevice};
+use init::InitPolicy;
+use interrupts::Interrupts;
 use lazy_static::lazy_static;
-use pyo3::exceptions::PyBaseException;
+use numbers::NumberFormat;
+use protection::Protection;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
 use pyo3::prelude::*;
 use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use stack::Stack;
 use std::collections::HashMap;
-use std::env;
 use std::fmt;
 use std::fmt::Write as FmtWrite;
-use std::fs;
+use syntax::{Statement, StatementKind, SyntaxTree};
 
 #[derive(Debug)]
 enum CustomError {
@@ -16,7 +42,9 @@ enum CustomError {
     ParseFloat(std::num::ParseFloatError),
     Regex(regex::Error),
     Format(std::fmt::Error),
+    Json(serde_json::Error),
     Instr(String),
+    Fault(Fault),
 }
 
 impl fmt::Display for CustomError {
@@ -27,14 +55,267 @@ impl fmt::Display for CustomError {
             CustomError::ParseFloat(ref err) => err.fmt(f),
             CustomError::Regex(ref err) => err.fmt(f),
             CustomError::Format(ref err) => err.fmt(f),
+            CustomError::Json(ref err) => err.fmt(f),
             CustomError::Instr(ref err) => write!(f, "{}", err),
+            CustomError::Fault(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl CustomError {
+    fn fault(kind: FaultKind, message: &str) -> CustomError {
+        CustomError::Fault(Fault {
+            kind,
+            message: message.to_string(),
+            pc: 0,
+            source: String::new(),
+            file: None,
+            line: None,
+        })
+    }
+
+    /// Attach the ROM index and source of the faulty instruction.
+    fn at(self, pc: usize, source: &str) -> CustomError {
+        match self {
+            CustomError::Fault(err) => CustomError::Fault(Fault {
+                pc,
+                source: source.to_string(),
+                ..err
+            }),
+            CustomError::Instr(err) => CustomError::Instr(format!("{}: {}", err, source)),
+            err => err,
+        }
+    }
+
+    /// Attach the source file and line of the faulty instruction, when known.
+    fn in_source(self, file: Option<&str>, line: Option<usize>) -> CustomError {
+        match self {
+            CustomError::Fault(err) => CustomError::Fault(Fault {
+                file: file.map(str::to_string).or(err.file),
+                line: line.or(err.line),
+                ..err
+            }),
+            err => err,
+        }
+    }
+}
+
+fn bad_register() -> CustomError {
+    CustomError::fault(
+        FaultKind::RegisterOutOfRange,
+        "Index of register out of bounds.",
+    )
+}
+
+fn bad_address(addr: i16) -> CustomError {
+    CustomError::fault(
+        FaultKind::MemoryOutOfBounds { addr: addr.into() },
+        "Index of memory out of bounds.",
+    )
+}
+
+fn bad_operand(message: &str) -> CustomError {
+    CustomError::fault(FaultKind::BadOperand, message)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum FaultKind {
+    UnknownInstruction,
+    BadOperand,
+    RegisterOutOfRange,
+    ImmediateOutOfRange,
+    JumpOutOfRange,
+    DuplicateLabel,
+    MemoryOutOfBounds { addr: i32 },
+    DeviceError,
+    IllegalInstruction,
+    EndOfRom,
+    InstrLimit,
+    UninitializedRead,
+    ConventionViolation,
+    StackOverflow { addr: i32 },
+    StackUnderflow { addr: i32 },
+    StackCollision { addr: i32 },
+    WriteProtected { addr: i32 },
+    AccessViolation { addr: i32 },
+}
+
+impl FaultKind {
+    fn name(&self) -> &'static str {
+        match self {
+            FaultKind::UnknownInstruction => "UnknownInstruction",
+            FaultKind::BadOperand => "BadOperand",
+            FaultKind::RegisterOutOfRange => "RegisterOutOfRange",
+            FaultKind::ImmediateOutOfRange => "ImmediateOutOfRange",
+            FaultKind::JumpOutOfRange => "JumpOutOfRange",
+            FaultKind::DuplicateLabel => "DuplicateLabel",
+            FaultKind::MemoryOutOfBounds { .. } => "MemoryOutOfBounds",
+            FaultKind::DeviceError => "DeviceError",
+            FaultKind::IllegalInstruction => "IllegalInstruction",
+            FaultKind::EndOfRom => "EndOfRom",
+            FaultKind::InstrLimit => "InstrLimit",
+            FaultKind::UninitializedRead => "UninitializedRead",
+            FaultKind::ConventionViolation => "ConventionViolation",
+            FaultKind::StackOverflow { .. } => "StackOverflow",
+            FaultKind::StackUnderflow { .. } => "StackUnderflow",
+            FaultKind::StackCollision { .. } => "StackCollision",
+            FaultKind::WriteProtected { .. } => "WriteProtected",
+            FaultKind::AccessViolation { .. } => "AccessViolation",
+        }
+    }
+
+    fn is_runtime(&self) -> bool {
+        matches!(
+            self,
+            FaultKind::MemoryOutOfBounds { .. }
+                | FaultKind::DeviceError
+                | FaultKind::IllegalInstruction
+                | FaultKind::EndOfRom
+                | FaultKind::InstrLimit
+                | FaultKind::UninitializedRead
+                | FaultKind::ConventionViolation
+                | FaultKind::StackOverflow { .. }
+                | FaultKind::StackUnderflow { .. }
+                | FaultKind::StackCollision { .. }
+                | FaultKind::WriteProtected { .. }
+                | FaultKind::AccessViolation { .. }
+        )
+    }
+}
+
+/// An assembly error or a runtime fault, located at the instruction that caused it.
+#[pyclass]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fault {
+    kind: FaultKind,
+    #[pyo3(get)]
+    message: String,
+    #[pyo3(get)]
+    pc: usize,
+    #[pyo3(get)]
+    source: String,
+    /// Source file, when the program was read from one.
+    #[pyo3(get)]
+    #[serde(default)]
+    file: Option<String>,
+    /// Source line, starting at 1.
+    #[pyo3(get)]
+    #[serde(default)]
+    line: Option<usize>,
+}
+
+#[pymethods]
+impl Fault {
+    #[getter]
+    fn kind(&self) -> &str {
+        self.kind.name()
+    }
+
+    #[getter]
+    fn addr(&self) -> Option<i32> {
+        match self.kind {
+            FaultKind::MemoryOutOfBounds { addr }
+            | FaultKind::StackOverflow { addr }
+            | FaultKind::StackUnderflow { addr }
+            | FaultKind::StackCollision { addr }
+            | FaultKind::WriteProtected { addr }
+            | FaultKind::AccessViolation { addr } => Some(addr),
+            _ => None,
+        }
+    }
+
+    #[getter]
+    fn is_runtime(&self) -> bool {
+        self.kind.is_runtime()
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.source.is_empty() {
+            write!(f, "{}", self.message)
+        } else if let Some(line) = self.line {
+            write!(
+                f,
+                "{} ({}, instruction {}, {})",
+                self.message,
+                self.source,
+                self.pc,
+                source_location(self.file.as_deref(), line)
+            )
+        } else {
+            write!(
+                f,
+                "{} ({}, instruction {})",
+                self.message, self.source, self.pc
+            )
         }
     }
 }
 
+/// `file:line`, or `line n` for code that does not come from a file.
+fn source_location(file: Option<&str>, line: usize) -> String {
+    match file {
+        Some(file) => format!("{}:{}", file, line),
+        None => format!("line {}", line),
+    }
+}
+
+/// Where each ROM instruction comes from, so that messages can point at the source,
+/// and the data words laid out after the code.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SourceMap {
+    /// Name of the source file, None for code given as a string.
+    file: Option<String>,
+    /// Source line, starting at 1, of each ROM instruction.
+    lines: Vec<usize>,
+    /// Words of the `.fill` and `.space` directives, in RAM from address `lines.len()`.
+    #[serde(default)]
+    data: Vec<i16>,
+}
+
+impl SourceMap {
+    /// RAM addresses of the data words.
+    fn data_range(&self) -> std::ops::Range<usize> {
+        self.lines.len()..self.lines.len() + self.data.len()
+    }
+
+    fn line(&self, pc: usize) -> Option<usize> {
+        self.lines.get(pc).copied()
+    }
+
+    fn location(&self, pc: usize) -> Option<String> {
+        self.line(pc)
+            .map(|line| source_location(self.file.as_deref(), line))
+    }
+
+    /// Attach the source location of the faulty instruction to `err`.
+    fn locate(&self, err: CustomError) -> CustomError {
+        let line = match &err {
+            CustomError::Fault(fault) if !fault.source.is_empty() => self.line(fault.pc),
+            _ => None,
+        };
+        err.in_source(self.file.as_deref(), line)
+    }
+}
+
+create_exception!(risc16_rs, AssemblyError, PyException);
+create_exception!(risc16_rs, ExecutionError, PyException);
+create_exception!(risc16_rs, InstructionLimitError, ExecutionError);
+
 impl From<CustomError> for PyErr {
     fn from(err: CustomError) -> PyErr {
-        PyBaseException::new_err(err.to_string())
+        match err {
+            CustomError::Fault(Fault {
+                kind: FaultKind::InstrLimit,
+                ..
+            }) => InstructionLimitError::new_err(err.to_string()),
+            CustomError::Fault(ref fault) if fault.kind.is_runtime() => {
+                ExecutionError::new_err(err.to_string())
+            }
+            CustomError::Json(_) => PyValueError::new_err(err.to_string()),
+            _ => AssemblyError::new_err(err.to_string()),
+        }
     }
 }
 
@@ -56,6 +337,12 @@ impl From<std::fmt::Error> for CustomError {
     }
 }
 
+impl From<serde_json::Error> for CustomError {
+    fn from(err: serde_json::Error) -> CustomError {
+        CustomError::Json(err)
+    }
+}
+
 impl From<std::io::Error> for CustomError {
     fn from(err: std::io::Error) -> CustomError {
         CustomError::Io(err)
@@ -81,6 +368,13 @@ impl From<std::num::ParseFloatError> for CustomError {
 }
 
 type RiscResult<T> = std::result::Result<T, CustomError>;
+type Rom = Vec<(String, Args)>;
+
+/// Words of RAM.
+const RAM_SIZE: usize = 256;
+
+/// Only r0, hardwired to 0, counts as written before a run.
+const UNINIT_REGS: [bool; 8] = [true, false, false, false, false, false, false, false];
 
 #[pyclass]
 struct Risc16 {
@@ -88,7 +382,7 @@ struct Risc16 {
     registers: [i16; 8],
     #[pyo3(get)]
     pc: usize,
-    ram: [i16; 256],
+    ram: [i16; RAM_SIZE],
     #[pyo3(get)]
     instr_count: u32,
     #[pyo3(get)]
@@ -98,25 +392,298 @@ struct Risc16 {
     arch: Archtype,
     #[pyo3(get)]
     buffer: String,
+    #[pyo3(get)]
+    strict: bool,
+    #[pyo3(get)]
+    error: Option<Fault>,
+    devices: Vec<Box<dyn Device>>,
+    interrupts: Interrupts,
+    init: InitPolicy,
+    initial_registers: [i16; 8],
+    initial_ram: [i16; RAM_SIZE],
+    /// Registers and RAM words written by the program or the test vector.
+    reg_written: [bool; 8],
+    ram_written: [bool; RAM_SIZE],
+    /// Locations read before being written by the instruction being executed.
+    uninit_pending: Vec<String>,
+    /// First read of each uninitialized location, as (pc, source, location).
+    #[pyo3(get)]
+    uninit_reads: Vec<(usize, String, String)>,
+    source_map: SourceMap,
+    /// Write every executed instruction to the buffer.
+    #[pyo3(get, set)]
+    trace: bool,
+    call_stack: CallStack,
+    convention: Option<Convention>,
+    /// Breaches of the calling convention, as (pc, message).
+    #[pyo3(get)]
+    convention_violations: Vec<(usize, String)>,
+    stack: Option<Stack>,
+    /// Registers holding a stack address: copies of the stack pointer and sums with them.
+    stack_derived: [bool; 8],
+    protection: Option<Protection>,
+}
+
+#[pymethods]
+impl Risc16 {
+    #[new]
+    #[args(
+        max_instr = "100000",
+        strict = "false",
+        arch = "\"IS0\"",
+        init = "\"zero\""
+    )]
+    fn py_new(max_instr: u32, strict: bool, arch: &str, init: &str) -> PyResult<Risc16> {
+        let mut proc = Risc16::new(Archtype::from_name(arch)?, max_instr, strict);
+        proc.devices = devices::default_devices(&[]);
+        proc.set_init(InitPolicy::from_spec(init)?);
+        Ok(proc)
+    }
+
+    /// Serialize registers, pc, memory, counters, labels and architecture.
+    #[name = "to_json"]
+    #[text_signature = "($self)"]
+    fn to_json_py(&self) -> PyResult<String> {
+        Ok(self.to_json()?)
+    }
+
+    /// Restore a Risc16 saved with `to_json`, with the default devices mapped.
+    ///
+    /// The calling convention, stack and memory protection checks are not saved,
+    /// set them again on the restored Risc16.
+    #[staticmethod]
+    #[name = "from_json"]
+    #[text_signature = "(json)"]
+    fn from_json_py(json: &str) -> PyResult<Risc16> {
+        let mut proc = Risc16::from_json(json)?;
+        proc.devices = devices::default_devices(&[]);
+        Ok(proc)
+    }
+
+    #[getter(arch)]
+    fn arch_py(&self) -> &str {
+        self.arch.name()
+    }
+
+    /// Set the label or ROM index where interrupts jump to (IS1 and IS2 only).
+    #[text_signature = "($self, handler)"]
+    fn set_interrupt_handler(&mut self, handler: String) {
+        self.interrupts.handler = Some(handler);
+    }
+
+    /// Raise the timer interrupt (line 0) every `period` instructions, None to disable it.
+    #[text_signature = "($self, period)"]
+    fn set_timer_interrupt(&mut self, period: Option<u32>) -> PyResult<()> {
+        if period == Some(0) {
+            return Err(PyValueError::new_err("The timer period must be at least 1"));
+        }
+        self.interrupts.timer_period = period;
+        Ok(())
+    }
+
+    /// Check calls and returns against a calling convention, None to stop checking.
+    ///
+    /// The spec is "default" (link r7, stack pointer r6, arguments r1-r3, callee saved r4-r5)
+    /// or `;` separated settings among `link=<reg>`, `sp=<reg>`, `args=<regs>` and `saved=<regs>`.
+    #[text_signature = "($self, spec)"]
+    fn set_convention(&mut self, spec: Option<&str>) -> PyResult<()> {
+        self.convention = spec.map(Convention::from_spec).transpose()?;
+        Ok(())
+    }
+
+    /// Check `lw` and `sw` through the stack pointer against a stack region, None to stop checking.
+    ///
+    /// The spec is "default" (r6, from 0xff down to 0) or `;` separated settings among
+    /// `sp=<reg>`, `top=<addr>` and `limit=<addr>`. Writes to the program data collide.
+    #[text_signature = "($self, spec)"]
+    fn set_stack(&mut self, spec: Option<&str>) -> PyResult<()> {
+        self.stack = spec.map(Stack::from_spec).transpose()?;
+        Ok(())
+    }
+
+    /// Protect memory regions against the program, None to remove the protection.
+    ///
+    /// The spec is `;` separated settings among `readonly=<first>-<last>,...` and
+    /// `noaccess=<first>-<last>,...`. Stores to read-only regions raise WriteProtected,
+    /// loads and stores to no-access regions raise AccessViolation.
+    #[text_signature = "($self, spec)"]
+    fn set_protection(&mut self, spec: Option<&str>) -> PyResult<()> {
+        self.protection = spec.map(Protection::from_spec).transpose()?;
+        Ok(())
+    }
+
+    /// Raise the external interrupt `line` (1 to 7) once `at` instructions have run.
+    #[text_signature = "($self, line, at)"]
+    fn schedule_interrupt(&mut self, line: u8, at: u32) -> PyResult<()> {
+        if !(1..=7).contains(&line) {
+            return Err(PyValueError::new_err(format!(
+                "Bad interrupt line: {}, external lines are 1 to 7",
+                line
+            )));
+        }
+        self.interrupts.schedule.push((at, line));
+        Ok(())
+    }
+
+    /// Map a Python object as a device on the addresses `first` to `last`.
+    ///
+    /// The object must provide `read(addr) -> int` and `write(addr, value)`,
+    /// and may provide `state() -> str` and `reset()`, called before every run.
+    #[name = "add_device"]
+    #[args(last = "None")]
+    #[text_signature = "($self, device, name, first, last=None)"]
+    fn add_device_py(&mut self, device: PyObject, name: String, first: u16, last: Option<u16>) {
+        let last = last.unwrap_or(first);
+        self.add_device(Box::new(PyDevice::new(name, first, last, device)));
+    }
+
+    /// Assemble and run `code` from a zeroed state, keeping the mapped devices but
+    /// resetting them.
+    ///
+    /// Faults are reported in `error`, raises AssemblyError if the code cannot be assembled.
+    #[text_signature = "($self, code)"]
+    fn run(&mut self, code: &str) -> PyResult<()> {
+        let (rom, labels, source_map) = assemble(code, self.strict, self.arch)?;
+        self.reset_state();
+        self.source_map = source_map;
+        let result = self
+            .devices
+            .iter_mut()
+            .try_for_each(|device| device.reset())
+            .and_then(|_| self.execute(&rom, &labels));
+        if let Err(e) = result {
+            self.fail(e);
+        }
+        Ok(())
+    }
+
+    /// Pc, instruction count, registers and the memory words changed by the run.
+    ///
+    /// Registers are written in `format`: "hex" (16 bit two's complement), "unsigned",
+    /// "signed" or "binary".
+    #[name = "print_state"]
+    #[args(format = "\"hex\"")]
+    #[text_signature = "($self, format=\"hex\")"]
+    fn print_state_py(&self, format: &str) -> PyResult<String> {
+        Ok(self.print_state(false, NumberFormat::from_name(format)?)?)
+    }
+
+    /// State of the devices that report one, by device name.
+    #[getter(device_states)]
+    fn device_states_py(&self) -> HashMap<String, String> {
+        self.devices
+            .iter()
+            .filter_map(|d| d.state().map(|state| (d.name().to_string(), state)))
+            .collect()
+    }
+
+    /// Text written to the console devices during the run.
+    #[getter(console)]
+    fn console_py(&self) -> String {
+        self.console()
+    }
+
+    /// Mapped devices as (name, first address, last address).
+    #[getter(devices)]
+    fn devices_py(&self) -> Vec<(String, u16, u16)> {
+        self.devices
+            .iter()
+            .map(|d| (d.name().to_string(), *d.range().start(), *d.range().end()))
+            .collect()
+    }
+
+    /// `len` memory words from `start`, in rows of 8 with their address, the words in every
+    /// format of `format` and the labels of the `.fill` and `.space` words in the row.
+    ///
+    /// `format` lists, separated by commas, any of "hex", "unsigned", "signed" and "binary".
+    #[name = "dump_memory"]
+    #[args(start = "0", len = "256", format = "\"hex,signed\"")]
+    #[text_signature = "($self, start=0, len=256, format=\"hex,signed\")"]
+    fn dump_memory_py(&self, start: u16, len: u16, format: &str) -> PyResult<String> {
+        Ok(self.dump_memory(start, len, &NumberFormat::list(format)?))
+    }
+
+    /// RAM words changed by the run, as {address: (label, old value, new value)}.
+    #[getter(memory_diff)]
+    fn memory_diff_py(&self) -> HashMap<u16, (Option<String>, i16, i16)> {
+        self.memory_diff()
+            .into_iter()
+            .map(|(addr, label, old, new)| (addr, (label, old, new)))
+            .collect()
+    }
+
+    /// Subroutines being executed as guessed from `jalr`, innermost first,
+    /// as (subroutine, ROM index, source line).
+    #[getter(backtrace)]
+    fn backtrace_py(&self) -> Vec<(String, usize, Option<usize>)> {
+        self.backtrace()
+            .into_iter()
+            .map(|(name, pc)| (name, pc, self.source_map.line(pc)))
+            .collect()
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 enum Archtype {
     IS0,
     IS1,
     IS2,
 }
 
+impl Archtype {
+    fn from_name(name: &str) -> RiscResult<Archtype> {
+        match name {
+            "IS0" => Ok(Archtype::IS0),
+            "IS1" => Ok(Archtype::IS1),
+            "IS2" => Ok(Archtype::IS2),
+            _ => Err(format!("Unknown architecture: {}", name).into()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Archtype::IS0 => "IS0",
+            Archtype::IS1 => "IS1",
+            Archtype::IS2 => "IS2",
+        }
+    }
+
+    /// The extended architectures have an interrupt controller and `reti`.
+    fn has_interrupts(&self) -> bool {
+        *self != Archtype::IS0
+    }
+}
+
 impl Risc16 {
-    fn new(arch: Archtype, max_instr: u32) -> Risc16 {
+    fn new(arch: Archtype, max_instr: u32, strict: bool) -> Risc16 {
         Risc16 {
             registers: [0; 8],
             pc: 0,
-            ram: [0; 256],
+            ram: [0; RAM_SIZE],
             instr_count: 0,
             max_instr,
             labels: HashMap::new(),
             arch,
             buffer: String::new(),
+            strict,
+            error: None,
+            devices: Vec::new(),
+            interrupts: Interrupts::default(),
+            init: InitPolicy::Zero,
+            initial_registers: [0; 8],
+            initial_ram: [0; RAM_SIZE],
+            reg_written: UNINIT_REGS,
+            ram_written: [false; RAM_SIZE],
+            uninit_pending: Vec::new(),
+            uninit_reads: Vec::new(),
+            source_map: SourceMap::default(),
+            trace: false,
+            call_stack: CallStack::default(),
+            convention: None,
+            convention_violations: Vec::new(),
+            stack: None,
+            stack_derived: [false; 8],
+            protection: None,
         }
     }
 
@@ -126,31 +693,357 @@ impl Risc16 {
         labels: &HashMap<String, usize>,
     ) -> RiscResult<bool> {
         self.labels = labels.to_owned();
-        for instr in 0..=self.max_instr {
-            let halt = self.execute_instr(
-                rom.get(self.pc)
-                    .ok_or("Reaching end of ROM, missing HALT")?,
+        self.prepare_run();
+        while self.step(rom)? {}
+        Ok(true)
+    }
+
+    /// Execute the instruction at pc, returns false once HALT is reached.
+    fn step(&mut self, rom: &[(String, Args)]) -> RiscResult<bool> {
+        let result = self.step_instr(rom);
+        result.map_err(|e| self.source_map.locate(e))
+    }
+
+    fn step_instr(&mut self, rom: &[(String, Args)]) -> RiscResult<bool> {
+        if self.arch.has_interrupts() {
+            self.take_interrupt()?;
+        }
+        let full_instr = rom.get(self.pc).ok_or_else(|| {
+            CustomError::fault(FaultKind::EndOfRom, "Reaching end of ROM, missing HALT")
+                .at(self.pc, "")
+        })?;
+        let pc = self.pc;
+        let source = format!("{} {}", full_instr.0, full_instr.1);
+        if self.trace {
+            let whereabouts = self.whereabouts(pc);
+            writeln!(
+                self.buffer,
+                "Trace: {} ({})",
+                source.trim_end(),
+                whereabouts
+            )?;
+        }
+        let result = self.execute_instr(full_instr);
+        self.report_uninit(pc, &source)?;
+        let halt = result.map_err(|e| e.at(pc, &source))?;
+        self.registers[0] = 0;
+        if !halt {
+            return Ok(false);
+        } else if self.instr_count == self.max_instr {
+            return Err(CustomError::fault(
+                FaultKind::InstrLimit,
+                "Reaching max instruction count, missing HALT or infinite loop ?",
+            )
+            .at(pc, &source));
+        }
+        self.instr_count += 1;
+        // the pc is a 16 bits register
+        self.pc = self.pc.wrapping_add(1) as u16 as usize;
+        Ok(true)
+    }
+
+    fn take_interrupt(&mut self) -> RiscResult<()> {
+        if let Some(line) = self.interrupts.poll(self.instr_count) {
+            let handler = self.interrupts.handler.clone().unwrap_or_default();
+            let target = resolve_imm(&handler, &self.labels).ok_or_else(|| {
+                bad_operand(&format!("Bad interrupt handler: {}", handler)).at(self.pc, "")
+            })?;
+            writeln!(
+                self.buffer,
+                "Interrupt {}: entering {} from instruction {}",
+                line, handler, self.pc
+            )?;
+            self.interrupts.saved_pc = Some(self.pc);
+            self.interrupts.cause = line;
+            self.pc = target as usize;
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, err: CustomError) {
+        writeln!(self.buffer, "Error! {}", err).unwrap();
+        if !self.call_stack.frames.is_empty() {
+            let backtrace = self.format_backtrace();
+            self.buffer.push_str(&backtrace);
+        }
+        if let CustomError::Fault(fault) = err {
+            self.error = Some(fault);
+        }
+    }
+
+    /// Subroutine and ROM index of each frame of the call stack, innermost first.
+    /// The outermost frame, not entered through a call, is named after the closest
+    /// label before its pc.
+    fn backtrace(&self) -> Vec<(String, usize)> {
+        let frames = &self.call_stack.frames;
+        // each frame runs until the call site of the next one, the innermost one is at the pc
+        let pcs: Vec<usize> = frames
+            .iter()
+            .map(|f| f.call_site)
+            .chain(std::iter::once(self.pc))
+            .collect();
+        let mut backtrace = vec![(self.closest_label(pcs[0]), pcs[0])];
+        for (frame, pc) in frames.iter().zip(&pcs[1..]) {
+            backtrace.push((self.subroutine_name(frame.target), *pc));
+        }
+        backtrace.reverse();
+        backtrace
+    }
+
+    fn format_backtrace(&self) -> String {
+        let mut text = String::from("Backtrace:\n");
+        for (i, (name, pc)) in self.backtrace().into_iter().enumerate() {
+            writeln!(text, "  #{} {} ({})", i, name, self.whereabouts(pc)).unwrap();
+        }
+        text
+    }
+
+    fn closest_label(&self, pc: usize) -> String {
+        self.labels
+            .iter()
+            .filter(|(_, index)| **index <= pc)
+            .max_by_key(|(label, index)| (**index, std::cmp::Reverse(*label)))
+            .map_or("main".to_string(), |(label, _)| label.clone())
+    }
+
+    /// The label of a subroutine entry, or its ROM index when it has none.
+    fn subroutine_name(&self, entry: usize) -> String {
+        self.labels
+            .iter()
+            .filter(|(_, index)| **index == entry)
+            .map(|(label, _)| label.clone())
+            .min()
+            .unwrap_or_else(|| format!("instruction {}", entry))
+    }
+
+    fn add_device(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+
+    fn load(&mut self, address: i16) -> RiscResult<i16> {
+        let addr = address as u16;
+        let instr_count = self.instr_count;
+        self.check_protection(addr, false)?;
+        if self.arch.has_interrupts() && addr == interrupts::CAUSE_ADDR {
+            return Ok(self.interrupts.cause.into());
+        }
+        if let Some(device) = self.devices.iter_mut().find(|d| d.range().contains(&addr)) {
+            return device.read(addr, instr_count);
+        }
+        let val = self
+            .ram
+            .get(addr as usize)
+            .copied()
+            .ok_or_else(|| bad_address(address))?;
+        if !self.ram_written[addr as usize] {
+            self.uninit_read(format!("mem[{}]", addr))?;
+        }
+        Ok(val)
+    }
+
+    fn store(&mut self, address: i16, val: i16) -> RiscResult<()> {
+        let addr = address as u16;
+        let instr_count = self.instr_count;
+        self.check_protection(addr, true)?;
+        if let Some(device) = self.devices.iter_mut().find(|d| d.range().contains(&addr)) {
+            return device.write(addr, val, instr_count);
+        }
+        let ram = self
+            .ram
+            .get_mut(addr as usize)
+            .ok_or_else(|| bad_address(address))?;
+        *ram = val;
+        self.ram_written[addr as usize] = true;
+        Ok(())
+    }
+
+    /// Check a memory access whose address comes from register `base`.
+    fn check_stack(&self, base: usize, address: i16, write: bool) -> RiscResult<()> {
+        match &self.stack {
+            Some(stack) if stack.stack_pointer == base || self.stack_derived[base] => {
+                stack.check(address as u16, write, self.source_map.data_range())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_protection(&self, addr: u16, write: bool) -> RiscResult<()> {
+        match &self.protection {
+            Some(protection) => protection.check(addr, write, self.source_map.lines.len()),
+            None => Ok(()),
+        }
+    }
+
+    fn read_reg(&mut self, reg: usize) -> RiscResult<i16> {
+        let val = *self.registers.get(reg).ok_or_else(bad_register)?;
+        if !self.reg_written[reg] {
+            self.uninit_read(format!("r{}", reg))?;
+        }
+        let frame = self.call_stack.frames.last();
+        if let (Some(convention), Some(frame)) = (&self.convention, frame) {
+            if !frame.written[reg] && reg != frame.link && !convention.may_read(reg) {
+                let message = format!(
+                    "{} reads r{} before writing it, it is not an argument",
+                    self.subroutine_name(frame.target),
+                    reg
+                );
+                self.convention_violation(message)?;
+            }
+        }
+        Ok(val)
+    }
+
+    fn write_reg(&mut self, reg: usize, val: i16) -> RiscResult<()> {
+        *self.registers.get_mut(reg).ok_or_else(bad_register)? = val;
+        self.reg_written[reg] = true;
+        self.stack_derived[reg] = false;
+        self.call_stack.written(reg);
+        Ok(())
+    }
+
+    /// Write the sum of `sources` to `reg`, a stack address if one of them is.
+    fn write_sum(&mut self, reg: usize, val: i16, sources: &[usize]) -> RiscResult<()> {
+        let derived = match &self.stack {
+            Some(stack) => sources
+                .iter()
+                .any(|&src| src == stack.stack_pointer || self.stack_derived[src]),
+            None => false,
+        };
+        self.write_reg(reg, val)?;
+        // r0 stays 0
+        self.stack_derived[reg] = derived && reg != 0;
+        Ok(())
+    }
+
+    /// Check the link register of a call.
+    fn check_call(&mut self, link: usize) -> RiscResult<()> {
+        match &self.convention {
+            Some(convention) if convention.link != link => {
+                let message = format!(
+                    "call links through r{} instead of r{}",
+                    link, convention.link
+                );
+                self.convention_violation(message)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the subroutine of `frame` gives back the callee saved registers
+    /// and the stack pointer as they were at the call.
+    fn check_return(&mut self, frame: &Frame) -> RiscResult<()> {
+        let convention = match &self.convention {
+            Some(convention) => convention.clone(),
+            None => return Ok(()),
+        };
+        let name = self.subroutine_name(frame.target);
+        let sp = convention.stack_pointer;
+        if self.registers[sp] != frame.registers[sp] {
+            let message = format!(
+                "{} leaves the stack pointer r{} unbalanced: {:#06x} at the call, {:#06x} on return",
+                name, sp, frame.registers[sp], self.registers[sp]
+            );
+            self.convention_violation(message)?;
+        }
+        for &reg in &convention.callee_saved {
+            // the link register of the call holds the return address, not the caller's value
+            if reg != sp && reg != frame.link && self.registers[reg] != frame.registers[reg] {
+                let message = format!(
+                    "{} does not restore r{}: {:#06x} at the call, {:#06x} on return",
+                    name, reg, frame.registers[reg], self.registers[reg]
+                );
+                self.convention_violation(message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fault in strict mode, otherwise warn once per instruction and message.
+    fn convention_violation(&mut self, message: String) -> RiscResult<()> {
+        if self.strict {
+            return Err(CustomError::fault(FaultKind::ConventionViolation, &message));
+        }
+        let pc = self.pc;
+        if !self
+            .convention_violations
+            .iter()
+            .any(|(at, seen)| *at == pc && *seen == message)
+        {
+            writeln!(
+                self.buffer,
+                "WARNING, calling convention: {} ({})",
+                message,
+                self.whereabouts(pc)
             )?;
-            self.registers[0] = 0;
-            if !halt {
-                break;
-            } else if instr == self.max_instr {
-                return Err(CustomError::Instr(
-                    "Reaching max instruction count, missing HALT or infinite loop ?".into(),
+            self.convention_violations.push((pc, message));
+        }
+        Ok(())
+    }
+
+    /// A location is read before anything was written to it: fault in strict mode,
+    /// otherwise keep it to be reported once the instruction is done.
+    fn uninit_read(&mut self, location: String) -> RiscResult<()> {
+        if self.strict {
+            return Err(CustomError::fault(
+                FaultKind::UninitializedRead,
+                &format!("Read of uninitialized {}", location),
+            ));
+        }
+        self.uninit_pending.push(location);
+        Ok(())
+    }
+
+    /// Warn about the uninitialized reads of the instruction at `pc`, once per location.
+    fn report_uninit(&mut self, pc: usize, source: &str) -> RiscResult<()> {
+        for location in std::mem::take(&mut self.uninit_pending) {
+            let seen = self
+                .uninit_reads
+                .iter()
+                .any(|(at, _, loc)| *at == pc && *loc == location);
+            if !seen {
+                writeln!(
+                    self.buffer,
+                    "WARNING, read of uninitialized {} ({}, {})",
+                    location,
+                    source,
+                    self.whereabouts(pc)
+                )?;
+                self.uninit_reads.push((pc, source.to_string(), location));
+            }
+        }
+        Ok(())
+    }
+
+    /// ROM index of an instruction, with its source location when known.
+    fn whereabouts(&self, pc: usize) -> String {
+        match self.source_map.location(pc) {
+            Some(location) => format!("instruction {}, {}", pc, location),
+            None => format!("instruction {}", pc),
+        }
+    }
+
+    fn console(&self) -> String {
+        self.devices.iter().filter_map(|d| d.output()).collect()
+    }
+
+    fn fit_imm7(&mut self, imm: i32) -> RiscResult<i16> {
+        // RRI immediates are 7 bits signed fields, the hardware only sees the low bits
+        if !(-64..=63).contains(&imm) {
+            if self.strict {
+                return Err(CustomError::fault(
+                    FaultKind::ImmediateOutOfRange,
+                    &format!("Immediate Too BIG : {}", imm),
                 ));
             }
-            self.instr_count += 1;
-            self.pc += 1;
+            // println!("/!\\ Immediate Too BIG : {}", imm);
+            writeln!(self.buffer, "/!\\ Immediate Too BIG : {}", imm)?;
         }
-        Ok(true)
+        Ok(((imm << 9) as i16) >> 9)
     }
 
     fn execute_instr(&mut self, full_instr: &(String, Args)) -> RiscResult<bool> {
         // self.display_state(false);
         let (instr, args) = full_instr;
-        // FIXME TODO exec trace:
-        // println!("{} {}", instr, args);
-        // writeln!(self.buffer, "{} {}", instr, args)?;
 
         match instr.as_str() {
             "nop" => self.nop(args).ok_or("nop error".into()),
@@ -165,9 +1058,13 @@ impl Risc16 {
             "sw" => self.sw(args),
             "beq" => self.beq(args),
             "jalr" => self.jalr(args),
+            "reti" if self.arch.has_interrupts() => self.reti(args),
             _ => {
                 // println!("Error: Instr not know: {}", instr);
-                Err("Error: Instr not know".into())
+                Err(CustomError::fault(
+                    FaultKind::UnknownInstruction,
+                    "Error: Instr not know",
+                ))
             }
         }
     }
@@ -175,247 +1072,336 @@ impl Risc16 {
     fn reset_state(&mut self) {
         self.registers = [0; 8];
         self.pc = 0;
-        self.ram = [0; 256];
+        self.ram = [0; RAM_SIZE];
         self.instr_count = 0;
         self.labels = HashMap::new();
         self.buffer = String::new();
+        self.error = None;
+        self.interrupts.reset();
+        self.reg_written = UNINIT_REGS;
+        self.ram_written = [false; RAM_SIZE];
+        self.uninit_pending.clear();
+        self.uninit_reads.clear();
+        self.call_stack.frames.clear();
+        self.convention_violations.clear();
+        self.stack_derived = [false; 8];
+        self.init.apply(&mut self.registers, &mut self.ram);
     }
 
-    fn display_state(&mut self, full: bool) {
-        print!("PC: {}, Instr. count: {}", self.pc, self.instr_count);
-        write!(
-            self.buffer,
-            "PC: {}, Instr. count: {}",
-            self.pc, self.instr_count
-        )
-        .unwrap();
-        println!(", regs: {:x?}", self.registers);
-        writeln!(self.buffer, ", regs: {:x?}", self.registers).unwrap();
-        if full {
-            println!("ram: {:?}", self.ram);
-        }
+    fn set_init(&mut self, init: InitPolicy) {
+        self.init = init;
+        self.init.apply(&mut self.registers, &mut self.ram);
     }
 
-    fn print_state(&mut self, full: bool) -> RiscResult<String> {
-        let mut state: String = String::from("");
-        write!(state, "PC: {}, Instr. count: {}", self.pc, self.instr_count)?;
-        writeln!(state, ", regs: {:x?}", self.registers)?;
-        if full {
-            writeln!(state, "ram: {:?}", self.ram)?;
+    /// Lay out the data words of the program in RAM, then keep the registers and RAM the
+    /// program starts with, to compare the final state against.
+    fn prepare_run(&mut self) {
+        let data = self.source_map.data_range();
+        for (addr, word) in data.zip(&self.source_map.data) {
+            if let Some(ram) = self.ram.get_mut(addr) {
+                *ram = *word;
+                self.ram_written[addr] = true;
+            }
         }
-        Ok(state)
+        self.initial_registers = self.registers;
+        self.initial_ram = self.ram;
+    }
+
+    /// RAM words whose value changed since the run started, as (address, label, old, new).
+    /// The label is the one of a `.fill` or `.space` word at the address.
+    fn memory_diff(&self) -> Vec<(u16, Option<String>, i16, i16)> {
+        (0..self.ram.len())
+            .filter(|&addr| self.ram[addr] != self.initial_ram[addr])
+            .map(|addr| {
+                let label = self.data_labels(addr..addr + 1).into_iter().next();
+                let label = label.map(|(_, label)| label);
+                (addr as u16, label, self.initial_ram[addr], self.ram[addr])
+            })
+            .collect()
+    }
+
+    /// Labels of the data words in `addresses`, as (address, label) sorted by address.
+    fn data_labels(&self, addresses: std::ops::Range<usize>) -> Vec<(usize, String)> {
+        let data = self.source_map.data_range();
+        let mut labels: Vec<(usize, String)> = self
+            .labels
+            .iter()
+            .filter(|(_, addr)| data.contains(addr) && addresses.contains(addr))
+            .map(|(label, addr)| (*addr, label.clone()))
+            .collect();
+        labels.sort();
+        labels
     }
 
-    fn process_string_args(&self, arg: &str) -> Option<i16> {
-        if let Some(result) = arg.strip_prefix("0x") {
-            match i32::from_str_radix(result, 16) {
-                Ok(result) => Some(result as i16),
-                _ => None,
+    /// `len` words from `start` in rows of 8, each row giving its address, the words in
+    /// every one of `formats` and the labels of the data words in it. Words outside the
+    /// RAM are shown as dashes.
+    fn dump_memory(&self, start: u16, len: u16, formats: &[NumberFormat]) -> String {
+        let mut text = String::new();
+        for row in (0..len).step_by(8) {
+            let first = start.wrapping_add(row);
+            let count = (len - row).min(8);
+            write!(text, "{:#06x}:", first).unwrap();
+            for format in formats {
+                text.push(' ');
+                for i in 0..8 {
+                    let cell = match self.ram.get(first.wrapping_add(i) as usize) {
+                        _ if i >= count => String::new(),
+                        Some(val) => format.render(*val),
+                        None => "-".repeat(format.width()),
+                    };
+                    write!(text, " {:>width$}", cell, width = format.width()).unwrap();
+                }
             }
-        } else if let Some(result) = arg.strip_prefix("0b") {
-            match i32::from_str_radix(result, 2) {
-                Ok(result) => Some(result as i16),
-                _ => None,
+            // the row may run past the RAM or wrap around 0xffff
+            let end = (first as usize + count as usize).min(self.ram.len());
+            let labels: Vec<String> = self
+                .data_labels(first as usize..end)
+                .into_iter()
+                .map(|(addr, label)| format!("{}={:#06x}", label, addr))
+                .collect();
+            if labels.is_empty() {
+                text.truncate(text.trim_end_matches(' ').len());
+                writeln!(text).unwrap();
+            } else {
+                writeln!(text, "  {}", labels.join(" ")).unwrap();
             }
-        } else if let Ok(result) = arg.parse::<i32>() {
-            Some(result as i16)
-        } else {
-            None
         }
+        text
     }
 
-    fn halt(&self, _args: &Args) -> Option<bool> {
-        Some(false)
+    /// One line per changed RAM word, values in hex, signed and unsigned.
+    fn format_memory_diff(&self) -> String {
+        let diff = self.memory_diff();
+        if diff.is_empty() {
+            return String::from("Memory changes: none\n");
+        }
+        let mut text = String::from("Memory changes:\n");
+        for (addr, label, old, new) in diff {
+            write!(text, "  {:#06x}", addr).unwrap();
+            if let Some(label) = label {
+                write!(text, " ({})", label).unwrap();
+            }
+            writeln!(
+                text,
+                ": {:#06x} ({}, {}) -> {:#06x} ({}, {})",
+                old as u16, old, old as u16, new as u16, new, new as u16
+            )
+            .unwrap();
+        }
+        text
     }
 
-    fn nop(&self, _args: &Args) -> Option<bool> {
-        Some(true)
+    /// Same final registers and fault as `other`, ignoring registers neither run wrote.
+    fn same_outcome(&self, other: &Risc16) -> bool {
+        let registers = (0..8).all(|i| {
+            let untouched = self.registers[i] == self.initial_registers[i]
+                && other.registers[i] == other.initial_registers[i];
+            untouched || self.registers[i] == other.registers[i]
+        });
+        let error = self.error.as_ref().map(|e| e.kind.clone());
+        registers && error == other.error.as_ref().map(|e| e.kind.clone())
     }
 
-    fn reset(&mut self, _args: &Args) -> RiscResult<bool> {
-        //&str
-        self.jalr(&Args::A23(vec![0, 0]))?;
-        Ok(true)
+    fn display_state(&mut self, full: bool) {
+        let state = self.state_line(NumberFormat::Hex);
+        println!("{}", state);
+        writeln!(self.buffer, "{}", state).unwrap();
+        if full {
+            let formats = [NumberFormat::Hex, NumberFormat::Signed];
+            print!("{}", self.dump_memory(0, self.ram.len() as u16, &formats));
+            print!("{}", self.format_memory_diff());
+        }
     }
 
-    fn add(&mut self, args: &Args) -> RiscResult<bool> {
+    /// Pc, instruction count and registers written in `format`, with the changed memory
+    /// words when there are some or when `full` is set.
+    fn print_state(&self, full: bool, format: NumberFormat) -> RiscResult<String> {
+        let mut state = self.state_line(format);
+        state.push('\n');
+        if full || self.ram != self.initial_ram {
+            state.push_str(&self.format_memory_diff());
+        }
+        Ok(state)
+    }
+
+    fn state_line(&self, format: NumberFormat) -> String {
+        let mut state = format!("PC: {}", self.pc);
+        if let Some(location) = self.source_map.location(self.pc) {
+            write!(state, " ({})", location).unwrap();
+        }
+        let registers: Vec<String> = self.registers.iter().map(|r| format.render(*r)).collect();
+        write!(
+            state,
+            ", Instr. count: {}, regs: [{}]",
+            self.instr_count,
+            registers.join(", ")
+        )
+        .unwrap();
+        state
+    }
+
+    fn halt(&self, _args: &Args) -> Option<bool> {
+        Some(false)
+    }
+
+    fn nop(&self, _args: &Args) -> Option<bool> {
+        Some(true)
+    }
+
+    fn reset(&mut self, _args: &Args) -> RiscResult<bool> {
+        //&str
+        self.jalr(&Args::A23(vec![0, 0]))?;
+        self.call_stack.frames.clear();
+        Ok(true)
+    }
+
+    fn add(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A23(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //Vec<usize>
-        let val1 = *self.registers.get(args[1]).ok_or("")?;
-        let val2 = *self.registers.get(args[2]).ok_or("")?;
-        let reg = self
-            .registers
-            .get_mut(args[0])
-            .ok_or("Index of register out of bounds.")?;
-        *reg = val1.wrapping_add(val2);
+        let val1 = self.read_reg(args[1])?;
+        let val2 = self.read_reg(args[2])?;
+        self.write_sum(args[0], val1.wrapping_add(val2), &args[1..])?;
         Ok(true)
     }
 
     fn addi(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A2i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, usize, String)
-        let imm = match self.labels.get(&args.2) {
-            Some(res) => *res as i32,
-            None => match self.process_string_args(&args.2) {
-                Some(res) => res.into(),
-                _ => {
-                    // println!("Impossible to parse jump");
-                    writeln!(self.buffer, "Error processing label/imm")?;
-                    return Err("Error processing label/imm".into());
-                }
-            },
-        } as i16;
-        if imm > 63 || imm < -64 {
-            // println!("/!\\ Immediate Too BIG : {}", imm);
-            writeln!(self.buffer, "/!\\ Immediate Too BIG : {}", imm).unwrap();
-        }
-        let val = *self.registers.get(args.1).ok_or("")?;
-        let reg = self
-            .registers
-            .get_mut(args.0)
-            .ok_or("Index of register out of bounds.")?;
-        *reg = val.wrapping_add(imm);
+        let imm = match resolve_imm(&args.2, &self.labels) {
+            Some(res) => res,
+            _ => {
+                // println!("Impossible to parse jump");
+                writeln!(self.buffer, "Error processing label/imm")?;
+                return Err(bad_operand("Error processing label/imm"));
+            }
+        };
+        let imm = self.fit_imm7(imm)?;
+        let val = self.read_reg(args.1)?;
+        self.write_sum(args.0, val.wrapping_add(imm), &[args.1])?;
         Ok(true)
     }
 
     fn nand(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A23(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //Vec<usize>
-        let val1 = *self.registers.get(args[1]).ok_or("")?;
-        let val2 = *self.registers.get(args[2]).ok_or("")?;
-        let reg = self
-            .registers
-            .get_mut(args[0])
-            .ok_or("Index of register out of bounds.")?;
-        *reg = !(val1 & val2);
+        let val1 = self.read_reg(args[1])?;
+        let val2 = self.read_reg(args[2])?;
+        self.write_reg(args[0], !(val1 & val2))?;
         Ok(true)
     }
 
     fn movi(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A1i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, String)
-        let val = match self.labels.get(&args.1) {
-            Some(res) => *res as i32,
-            None => match self.process_string_args(&args.1) {
-                Some(res) => res.into(),
-                _ => {
-                    // println!("Impossible to parse jump");
-                    writeln!(self.buffer, "Error processing label/imm")?;
-                    return Err("Error processing label/imm".into());
-                }
-            },
+        let val = match resolve_imm(&args.1, &self.labels) {
+            Some(res) => res,
+            _ => {
+                // println!("Impossible to parse jump");
+                writeln!(self.buffer, "Error processing label/imm")?;
+                return Err(bad_operand("Error processing label/imm"));
+            }
         } as i16;
 
-        let reg = self
-            .registers
-            .get_mut(args.0)
-            .ok_or("Index of register out of bounds.")?;
-        *reg = val;
+        self.write_reg(args.0, val)?;
         Ok(true)
     }
 
     fn lui(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A1i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, String)
-        let imm = self
-            .process_string_args(&args.1)
-            .ok_or("Error processing label/imm")?;
-        if imm > 1023 || imm < 0 {
+        let imm = match resolve_imm(&args.1, &self.labels) {
+            Some(res) => res,
+            _ => {
+                writeln!(self.buffer, "Error processing label/imm")?;
+                return Err(bad_operand("Error processing label/imm"));
+            }
+        };
+        if !(0..=1023).contains(&imm) {
+            if self.strict {
+                return Err(CustomError::fault(
+                    FaultKind::ImmediateOutOfRange,
+                    &format!("Immediate Too BIG for lui: {}", imm),
+                ));
+            }
             // println!("/!\\ Immediate Too BIG : {}", imm);
             writeln!(self.buffer, "/!\\ Immediate Too BIG : {}", imm)?;
         }
-        let reg = self
-            .registers
-            .get_mut(args.0)
-            .ok_or("Index of register out of bounds.")?;
-        *reg = imm.wrapping_shl(5);
+        // the 10 bits immediate goes to the top of the register, low 6 bits are zeroed
+        self.write_reg(args.0, ((imm & 0x3ff) << 6) as i16)?;
         Ok(true)
     }
 
     fn lw(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A2i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, usize, String)
-        let imm = self
-            .process_string_args(&args.2)
-            .ok_or("Error processing label/imm")?;
-        if imm > 63 || imm < -64 {
-            // println!("/!\\ Immediate Too BIG : {}", imm);
-            writeln!(self.buffer, "/!\\ Immediate Too BIG : {}", imm)?;
-        }
-        let address = *self.registers.get(args.1).ok_or("")? + imm;
-        let reg = self
-            .registers
-            .get_mut(args.0)
-            .ok_or("Index of register out of bounds.")?;
-        let val = *self
-            .ram
-            .get(address as usize)
-            .ok_or("Index of memory out of bounds.")?;
-        *reg = val;
+        let imm = resolve_imm(&args.2, &self.labels)
+            .ok_or_else(|| bad_operand("Error processing label/imm"))?;
+        let imm = self.fit_imm7(imm)?;
+        let address = self.read_reg(args.1)?.wrapping_add(imm);
+        self.check_stack(args.1, address, false)?;
+        let val = self.load(address)?;
+        self.write_reg(args.0, val)?;
         Ok(true)
     }
 
     fn sw(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A2i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, usize, String)
-        let imm = self
-            .process_string_args(&args.2)
-            .ok_or("Error processing label/imm")?;
-        if imm > 63 || imm < -64 {
-            // println!("/!\\ Immediate Too BIG : {}", imm);
-            writeln!(self.buffer, "/!\\ Immediate Too BIG : {}", imm)?;
-        }
-        let address = *self.registers.get(args.1).ok_or("")? + imm;
-        let ram = self
-            .ram
-            .get_mut(address as usize)
-            .ok_or("Index of memory out of bounds.")?;
-        *ram = *self.registers.get(args.0).ok_or("")?;
+        let imm = resolve_imm(&args.2, &self.labels)
+            .ok_or_else(|| bad_operand("Error processing label/imm"))?;
+        let imm = self.fit_imm7(imm)?;
+        let address = self.read_reg(args.1)?.wrapping_add(imm);
+        let val = self.read_reg(args.0)?;
+        self.check_stack(args.1, address, true)?;
+        self.store(address, val)?;
         Ok(true)
     }
 
     fn beq(&mut self, args: &Args) -> RiscResult<bool> {
         let args = match args {
             Args::A2i(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
         };
         //(usize, usize, String)
-        if self.registers.get(args.1).ok_or("")? == self.registers.get(args.0).ok_or("")? {
-            let jump;
-            match self.labels.get(&args.2) {
-                Some(res) => jump = *res as i32 - 1 - self.pc as i32,
-                None => match self.process_string_args(&args.2) {
-                    Some(res) => jump = res.into(),
-                    _ => {
-                        // println!("Impossible to parse jump");
-                        writeln!(self.buffer, "Impossible to parse jump")?;
-                        return Err("Impossible to parse jump".into());
-                    }
-                },
-            }
+        if self.read_reg(args.1)? == self.read_reg(args.0)? {
+            let jump = match beq_offset(&args.2, self.pc, &self.labels) {
+                Some(jump) => jump,
+                None => {
+                    // println!("Impossible to parse jump");
+                    writeln!(self.buffer, "Impossible to parse jump")?;
+                    return Err(bad_operand("Impossible to parse jump"));
+                }
+            };
             // let jump = lab - self.pc as i32;
-            if jump < -64 || jump > 63 {
+            if !(-64..=63).contains(&jump) {
+                if self.strict {
+                    return Err(CustomError::fault(
+                        FaultKind::JumpOutOfRange,
+                        &format!("Jump too long: \"{}\" of size {}", &args.2, jump),
+                    ));
+                }
                 // println!("WARNING, Jump too long: \"{}\" of size {}", &args.2, jump);
                 writeln!(
                     self.buffer,
@@ -423,10 +1409,10 @@ impl Risc16 {
                     &args.2, jump
                 )?;
             }
-            self.pc = (self.pc as i32 + jump) as usize;
+            // the offset field is 7 bits signed, longer jumps wrap around
+            let jump = ((jump << 9) as i16 >> 9) as i32;
+            self.pc = (self.pc as i32 + jump) as u16 as usize;
             // println!("Jumping to: {}: {}, {}, ", self.pc, &args.2, jump);
-            //FIXME TODO exec trace
-            // writeln!(self.buffer, "Jumping to: {}: {}", self.pc, &args.2)?;
         }
         Ok(true)
     }
@@ -435,18 +1421,60 @@ impl Risc16 {
         //vec
         let args = match args {
             Args::A23(a) => a,
-            _ => return Err("Bad argument types".into()),
+            _ => return Err(bad_operand("Bad argument types")),
+        };
+        let val = self.read_reg(args[1])?;
+        let registers = self.registers;
+        self.write_reg(args[0], self.pc.wrapping_add(1) as u16 as i16)?;
+        let target = val as u16 as usize;
+        let transfer = self
+            .call_stack
+            .jalr(self.pc, args[0], args[1], target, registers);
+        let (frame, mismatch) = match transfer {
+            Transfer::Call => {
+                self.check_call(args[0])?;
+                (None, None)
+            }
+            Transfer::Return { frame, mismatch } => (Some(frame), mismatch),
+            Transfer::Jump => (None, None),
         };
-        let val = *self
-            .registers
-            .get(args[1])
-            .ok_or("Index of register out of bounds.")?;
-        let reg = self
-            .registers
-            .get_mut(args[0])
-            .ok_or("Index of register out of bounds.")?;
-        *reg = self.pc as i16 + 1;
-        self.pc = val as usize - 1;
+        match mismatch {
+            Some(Mismatch::Skipped(frames)) => writeln!(
+                self.buffer,
+                "WARNING, return to instruction {} skips {} unfinished call(s) ({})",
+                target,
+                frames,
+                self.whereabouts(self.pc)
+            )?,
+            Some(Mismatch::Elsewhere { expected }) => writeln!(
+                self.buffer,
+                "WARNING, mismatched return to instruction {}, expected {} ({})",
+                target,
+                expected,
+                self.whereabouts(self.pc)
+            )?,
+            None => (),
+        }
+        if let Some(frame) = frame {
+            self.check_return(&frame)?;
+        }
+        self.pc = target.wrapping_sub(1);
+        Ok(true)
+    }
+
+    fn reti(&mut self, _args: &Args) -> RiscResult<bool> {
+        let saved_pc = self.interrupts.saved_pc.take().ok_or_else(|| {
+            CustomError::fault(
+                FaultKind::IllegalInstruction,
+                "reti outside of an interrupt handler",
+            )
+        })?;
+        writeln!(
+            self.buffer,
+            "Interrupt {}: returning to instruction {}",
+            self.interrupts.cause, saved_pc
+        )?;
+        self.pc = saved_pc.wrapping_sub(1);
         Ok(true)
     }
 }
@@ -479,48 +1507,161 @@ impl fmt::Display for Args {
     }
 }
 
-fn process_args_vec(args: &str, len: usize) -> RiscResult<Args> {
+/// The `;` separated settings of a check option, like "sp=r6;top=0x7f", as (key, value)
+/// with no value for a setting without `=`. Settings left out keep their default value.
+fn spec_settings(spec: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|setting| match setting.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (setting, None),
+        })
+}
+
+/// A decimal, 0x hexadecimal or 0b binary number, left as is so that range checks
+/// see the written value rather than its truncation.
+fn process_string_args(arg: &str) -> Option<i32> {
+    if let Some(result) = arg.strip_prefix("0x") {
+        i32::from_str_radix(result, 16).ok()
+    } else if let Some(result) = arg.strip_prefix("0b") {
+        i32::from_str_radix(result, 2).ok()
+    } else {
+        arg.parse::<i32>().ok()
+    }
+}
+
+/// `val` as a 16 bits word, when it fits signed or unsigned.
+fn fit_word(val: i32) -> Option<i16> {
+    if (-0x8000..=0xffff).contains(&val) {
+        Some(val as i16)
+    } else {
+        None
+    }
+}
+
+fn resolve_imm(arg: &str, labels: &HashMap<String, usize>) -> Option<i32> {
+    // labels, numbers, hi(x)/lo(x) and sums or differences of those
+    let arg = arg.trim();
+    let mut depth = 0;
+    let mut split = None;
+    let mut prev = '+';
+    for (i, c) in arg.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '+' | '-' if depth == 0 && !"+-(".contains(prev) => split = Some(i),
+            _ => (),
+        }
+        if !c.is_whitespace() {
+            prev = c;
+        }
+    }
+    if let Some(i) = split {
+        let lhs = resolve_imm(&arg[..i], labels)?;
+        let rhs = resolve_imm(&arg[i + 1..], labels)?;
+        return match &arg[i..=i] {
+            "+" => Some(lhs.wrapping_add(rhs)),
+            _ => Some(lhs.wrapping_sub(rhs)),
+        };
+    }
+    if let Some(inner) = arg.strip_prefix("hi(").and_then(|a| a.strip_suffix(')')) {
+        // top 10 bits, as loaded by lui
+        Some((resolve_imm(inner, labels)? >> 6) & 0x3ff)
+    } else if let Some(inner) = arg.strip_prefix("lo(").and_then(|a| a.strip_suffix(')')) {
+        // bottom 6 bits, to be added with addi after a lui
+        Some(resolve_imm(inner, labels)? & 0x3f)
+    } else if let Some(res) = labels.get(arg) {
+        Some(*res as i32)
+    } else {
+        process_string_args(arg)
+    }
+}
+
+/// Offset of the beq at `pc` jumping to `target`: a number is the offset itself, a label
+/// or an expression of labels is the instruction to jump to.
+fn beq_offset(target: &str, pc: usize, labels: &HashMap<String, usize>) -> Option<i32> {
+    let value = resolve_imm(target, labels)?;
+    if process_string_args(target.trim()).is_some() {
+        Some(value)
+    } else {
+        Some(value - 1 - pc as i32)
+    }
+}
+
+fn process_register(arg: &str) -> RiscResult<usize> {
+    let reg = match arg.to_lowercase().as_str() {
+        "zero" => 0,
+        "sp" => 6,
+        "ra" => 7,
+        name => name
+            .strip_prefix('r')
+            .or_else(|| name.strip_prefix('$'))
+            .unwrap_or(name)
+            .parse::<usize>()
+            .map_err(|_| bad_operand(&format!("Bad register: {}", arg)))?,
+    };
+    if reg > 7 {
+        return Err(CustomError::fault(
+            FaultKind::RegisterOutOfRange,
+            &format!("Register out of range: {}", arg),
+        ));
+    }
+    Ok(reg)
+}
+
+fn process_args_vec(args: &[&str], len: usize) -> RiscResult<Args> {
     let vec_arg = args
-        .split(',')
-        .map(|x| x.trim().parse::<usize>())
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| CustomError::from(e))?;
+        .iter()
+        .map(|arg| process_register(arg))
+        .collect::<RiscResult<Vec<_>>>()?;
     if vec_arg.len() != len {
-        return Err("Wrong number of arguments".into());
+        return Err(bad_operand("Wrong number of arguments"));
     }
     Ok(Args::A23(vec_arg))
 }
 
-fn process_args_2i(args: &str) -> RiscResult<Args> {
-    let vec_arg: Vec<&str> = args.split(',').collect();
+fn process_args_2i(args: &[&str]) -> RiscResult<Args> {
+    if args.len() != 3 {
+        return Err(bad_operand("Wrong number of arguments"));
+    }
     Ok(Args::A2i((
-        vec_arg[0].trim().parse()?,
-        vec_arg[1].trim().parse()?,
-        vec_arg[2].trim().to_owned(),
+        process_register(args[0])?,
+        process_register(args[1])?,
+        args[2].to_owned(),
     )))
 }
 
-fn process_args_1i(args: &str) -> RiscResult<Args> {
-    let vec_arg: Vec<&str> = args.split(',').collect();
-    Ok(Args::A1i((
-        vec_arg[0].trim().parse()?,
-        vec_arg[1].trim().to_owned(),
-    )))
+fn process_args_mem(args: &[&str]) -> RiscResult<Args> {
+    // accepts both `lw 1,2,4` and `lw 1, 4(2)`
+    lazy_static! {
+        static ref RE_OFFSET: Regex = Regex::new(r"^(.*)\(\s*([^()\s]+)\s*\)$").unwrap();
+    }
+    if args.len() == 2 {
+        if let Some(cap) = RE_OFFSET.captures(args[1]) {
+            let imm = match cap[1].trim() {
+                "" => "0",
+                imm => imm,
+            };
+            return Ok(Args::A2i((
+                process_register(args[0])?,
+                process_register(&cap[2])?,
+                imm.to_owned(),
+            )));
+        }
+    }
+    process_args_2i(args)
 }
 
-fn process_line(line: &str) -> RiscResult<(String, Args)> {
-    lazy_static! {
-        static ref RE_SPC: Regex = Regex::new(r"\s+").unwrap();
-    }
-    let vec_instr: Vec<&str> = RE_SPC.splitn(line.trim(), 2).collect();
-    let instr = vec_instr
-        .get(0)
-        .ok_or(format!("get error: {:?}", vec_instr))?;
-    let args = vec_instr
-        .get(1)
-        // .ok_or(format!("get error: {:?}", vec_instr))?;
-        .unwrap_or(&"");
-    let processed_args = match *instr {
+fn process_args_1i(args: &[&str]) -> RiscResult<Args> {
+    if args.len() != 2 {
+        return Err(bad_operand("Wrong number of arguments"));
+    }
+    Ok(Args::A1i((process_register(args[0])?, args[1].to_owned())))
+}
+
+fn process_instr(instr: &str, args: &[&str]) -> RiscResult<(String, Args)> {
+    let processed_args = match instr {
         "nop" => Args::None(true),
         "halt" => Args::None(true),
         "reset" => Args::None(true),
@@ -529,202 +1670,667 @@ fn process_line(line: &str) -> RiscResult<(String, Args)> {
         "nand" => process_args_vec(args, 3)?,
         "movi" => process_args_1i(args)?,
         "lui" => process_args_1i(args)?,
-        "lw" => process_args_2i(args)?,
-        "sw" => process_args_2i(args)?,
+        "lw" => process_args_mem(args)?,
+        "sw" => process_args_mem(args)?,
         "beq" => process_args_2i(args)?,
         "jalr" => process_args_vec(args, 2)?,
+        "reti" => Args::None(true),
         _ => {
-            // println!("Error: Instr not know: {}", instr);
-            // writeln!(self.buffer,"Error: Instr not know: {}", instr).unwrap();
-            return Err("Error: Instruction unknow".into());
-            // Args::None(false)
+            return Err(CustomError::fault(
+                FaultKind::UnknownInstruction,
+                "Error: Instruction unknow",
+            ));
         }
     };
     Ok((instr.to_string(), processed_args))
 }
 
-fn load_rom(content: String) -> RiscResult<(Vec<(String, Args)>, HashMap<String, usize>)> {
-    let re_cmts = Regex::new(r"(?m)//.*?$")?;
-    let code_without_comments = re_cmts.replace_all(&content, "\n");
-    let re_labop = Regex::new(r"^(\S*):(.*)")?;
-    let mut instr: Vec<(String, Args)> = Vec::new();
-    let mut labels = HashMap::new();
-    let mut instr_counter = 0;
-    for line in code_without_comments.lines() {
-        if line.ends_with(':') {
-            labels.insert(line.replace(":", ""), instr_counter);
-        // println!("lab only: {}, i {}", line, instr_counter)
-        } else if re_labop.is_match(line.trim()) {
-            let cap = re_labop.captures(line.trim()).ok_or("Regex Problem")?;
-            labels.insert(cap[1].trim().to_owned(), instr_counter);
-            let l = match process_line(cap[2].trim()) {
-                Ok(l) => l,
-                Err(e) => return Err(format!("{}: {}", e, cap[2].trim()).into()),
-            };
-            instr.push(l);
-            instr_counter += 1;
-        } else if line.trim() == "" {
-            continue;
-        } else {
-            let l = match process_line(line.trim()) {
-                Ok(l) => l,
-                Err(e) => return Err(format!("{}: {}", e, line.trim()).into()),
-            };
-            instr.push(l);
-            instr_counter += 1;
+/// What a statement assembles to.
+enum Assembled {
+    Instr((String, Args)),
+    /// Data words as written, resolved once every label is known.
+    Data(Vec<String>),
+}
+
+/// Assemble one statement of the syntax tree.
+fn process_statement(tree: &SyntaxTree, statement: &Statement) -> RiscResult<Assembled> {
+    let name = tree.text(&statement.name);
+    let operands: Vec<&str> = statement.operands.iter().map(|op| tree.text(op)).collect();
+    if statement.kind == StatementKind::Directive {
+        return process_directive(name, &operands).map(Assembled::Data);
+    }
+    process_instr(name, &operands).map(Assembled::Instr)
+}
+
+/// Data words of `.fill <value>`, a number or a label, or of `.space <count>`, zeros.
+fn process_directive(name: &str, operands: &[&str]) -> RiscResult<Vec<String>> {
+    let operand = match operands {
+        [operand] => *operand,
+        _ => return Err(bad_operand(&format!("{} takes one operand", name))),
+    };
+    match name {
+        ".fill" => Ok(vec![operand.to_string()]),
+        ".space" => {
+            let count = process_string_args(operand)
+                .filter(|count| (0..=0xffff).contains(count))
+                .ok_or_else(|| bad_operand(&format!("Bad word count: {}", operand)))?;
+            Ok(vec![String::from("0"); count as usize])
         }
+        _ => Err(CustomError::fault(
+            FaultKind::UnknownInstruction,
+            &format!("Unknown directive: {}", name),
+        )),
     }
-    Ok((instr, labels))
 }
 
-fn format_code(instr: Vec<(String, Args)>, labels: HashMap<String, usize>) -> Vec<String> {
+fn load_rom(content: String) -> RiscResult<(Rom, HashMap<String, usize>)> {
+    load_rom_lines(content).map(|(rom, labels, _)| (rom, labels))
+}
+
+/// Like `load_rom`, with the source map giving the line of each instruction and the
+/// data words of the program.
+///
+/// The data words follow the code in memory, as they would in a RiSC-16 memory image:
+/// their labels are RAM addresses starting at the number of instructions.
+fn load_rom_lines(content: String) -> RiscResult<(Rom, HashMap<String, usize>, SourceMap)> {
+    let tree = SyntaxTree::parse(&content);
+    let mut instr: Rom = Vec::new();
+    let mut lines = Vec::new();
+    // data words with their source line
+    let mut data: Vec<(String, usize)> = Vec::new();
+    // labels name the next statement, whether code or data
+    let mut code_labels = HashMap::new();
+    let mut data_labels = HashMap::new();
+    let mut pending: Vec<&str> = Vec::new();
+    for line in &tree.lines {
+        for label in &line.labels {
+            let name = tree.text(label);
+            // same error as the language server reports
+            if code_labels.contains_key(name)
+                || data_labels.contains_key(name)
+                || pending.contains(&name)
+            {
+                let msg = format!("Label {} defined twice", name);
+                return Err(CustomError::fault(FaultKind::DuplicateLabel, &msg)
+                    .at(instr.len(), name)
+                    .in_source(None, Some(line.number + 1)));
+            }
+            pending.push(name);
+        }
+        if let Some(statement) = &line.statement {
+            let assembled = process_statement(&tree, statement).map_err(|e| {
+                e.at(instr.len(), tree.text(&statement.span))
+                    .in_source(None, Some(line.number + 1))
+            })?;
+            match assembled {
+                Assembled::Instr(l) => {
+                    code_labels.extend(pending.drain(..).map(|name| (name, instr.len())));
+                    instr.push(l);
+                    lines.push(line.number + 1);
+                }
+                Assembled::Data(words) => {
+                    data_labels.extend(pending.drain(..).map(|name| (name, data.len())));
+                    data.extend(words.into_iter().map(|word| (word, line.number + 1)));
+                }
+            }
+        }
+    }
+    code_labels.extend(pending.drain(..).map(|name| (name, instr.len())));
+
+    let mut labels: HashMap<String, usize> = code_labels
+        .into_iter()
+        .map(|(name, index)| (name.to_owned(), index))
+        .collect();
+    labels.extend(
+        data_labels
+            .into_iter()
+            .map(|(name, offset)| (name.to_owned(), instr.len() + offset)),
+    );
+    if instr.len() + data.len() > RAM_SIZE && !data.is_empty() {
+        let line = data[RAM_SIZE.saturating_sub(instr.len())].1;
+        let msg = format!(
+            "The program data runs past the {} words of RAM, from {:#06x} to {:#06x}",
+            RAM_SIZE,
+            instr.len(),
+            instr.len() + data.len() - 1
+        );
+        return Err(bad_operand(&msg).in_source(None, Some(line)));
+    }
+    let data = data
+        .iter()
+        .map(|(word, line)| {
+            resolve_imm(word, &labels)
+                .and_then(fit_word)
+                .ok_or_else(|| {
+                    bad_operand(&format!("Bad data word: {}", word)).in_source(None, Some(*line))
+                })
+        })
+        .collect::<RiscResult<_>>()?;
+    let source_map = SourceMap {
+        file: None,
+        lines,
+        data,
+    };
+    Ok((instr, labels, source_map))
+}
+
+fn check_rom(
+    rom: &[(String, Args)],
+    labels: &HashMap<String, usize>,
+    strict: bool,
+) -> RiscResult<()> {
+    // immediates that do not resolve, and in strict mode those that do not fit
+    // their field, are rejected before running anything, as an assembler would
+    for (pc, (instr, args)) in rom.iter().enumerate() {
+        check_instr(pc, instr, args, labels, strict)?;
+    }
+    Ok(())
+}
+
+/// Check that the immediate of the instruction at `pc` resolves and, when `strict`,
+/// that it fits its field.
+fn check_instr(
+    pc: usize,
+    instr: &str,
+    args: &Args,
+    labels: &HashMap<String, usize>,
+    strict: bool,
+) -> RiscResult<()> {
+    let (imm, range, kind) = match (instr, args) {
+        ("addi", Args::A2i(a)) | ("lw", Args::A2i(a)) | ("sw", Args::A2i(a)) => (
+            resolve_imm(&a.2, labels),
+            -64..=63,
+            FaultKind::ImmediateOutOfRange,
+        ),
+        ("beq", Args::A2i(a)) => (
+            beq_offset(&a.2, pc, labels),
+            -64..=63,
+            FaultKind::JumpOutOfRange,
+        ),
+        ("lui", Args::A1i(a)) => (
+            resolve_imm(&a.1, labels),
+            0..=1023,
+            FaultKind::ImmediateOutOfRange,
+        ),
+        ("movi", Args::A1i(a)) => (
+            resolve_imm(&a.1, labels),
+            -0x8000..=0xffff,
+            FaultKind::ImmediateOutOfRange,
+        ),
+        _ => return Ok(()),
+    };
+    let source = format!("{} {}", instr, args);
+    match imm {
+        Some(imm) if strict && !range.contains(&imm) => {
+            Err(CustomError::fault(kind, &format!("Immediate Too BIG : {}", imm)).at(pc, &source))
+        }
+        None => Err(bad_operand("Error processing label/imm").at(pc, &source)),
+        _ => Ok(()),
+    }
+}
+
+/// Check that the instructions of `rom` exist on `arch`.
+fn check_arch(rom: &[(String, Args)], arch: Archtype) -> RiscResult<()> {
+    if arch.has_interrupts() {
+        return Ok(());
+    }
+    match rom.iter().position(|(instr, _)| instr == "reti") {
+        Some(pc) => Err(CustomError::fault(
+            FaultKind::UnknownInstruction,
+            &format!(
+                "reti needs the interrupt controller of IS1 or IS2, not {}",
+                arch.name()
+            ),
+        )
+        .at(pc, format!("{} {}", rom[pc].0, rom[pc].1).trim_end())),
+        None => Ok(()),
+    }
+}
+
+fn assemble(
+    code: &str,
+    strict: bool,
+    arch: Archtype,
+) -> RiscResult<(Rom, HashMap<String, usize>, SourceMap)> {
+    let (rom, labels, source_map) = load_rom_lines(code.to_string())?;
+    check_arch(&rom, arch).map_err(|e| source_map.locate(e))?;
+    check_rom(&rom, &labels, strict).map_err(|e| source_map.locate(e))?;
+    Ok((rom, labels, source_map))
+}
+
+/// Like `assemble`, for a program read from `path`.
+fn assemble_file(
+    path: &str,
+    strict: bool,
+    arch: Archtype,
+) -> RiscResult<(Rom, HashMap<String, usize>, SourceMap)> {
+    let code = std::fs::read_to_string(path)?;
+    let (rom, labels, mut source_map) =
+        assemble(&code, strict, arch).map_err(|e| e.in_source(Some(path), None))?;
+    source_map.file = Some(path.to_string());
+    Ok((rom, labels, source_map))
+}
+
+fn format_code(instr: &[(String, Args)], labels: &HashMap<String, usize>) -> Vec<String> {
     let mut code_vec = instr
         .iter()
         .map(|(s, args)| format!("{} {}", s, args))
         .collect::<Vec<_>>();
 
-    for l in labels.iter() {
-        let s = code_vec.get_mut(*l.1).unwrap();
-        *s = format!("{}: {}", l.0, s);
+    // sorted so that several labels of one instruction always come in the same order
+    let mut labels: Vec<_> = labels.iter().collect();
+    labels.sort_by(|a, b| b.0.cmp(a.0));
+    for (label, index) in labels {
+        if let Some(s) = code_vec.get_mut(*index) {
+            *s = format!("{}: {}", label, s);
+        }
     }
     code_vec
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    println!("Lauching: {}", filename);
+/// Options shared by every run of a batch.
+#[derive(Clone)]
+struct RunConfig {
+    max_instr: u32,
+    trace: bool,
+    strict: bool,
+    init: InitPolicy,
+    input_queue: Vec<i16>,
+    devices: Option<Vec<(String, u16)>>,
+    convention: Option<Convention>,
+    stack: Option<Stack>,
+    protection: Option<Protection>,
+    arch: Archtype,
+}
 
-    let content = fs::read_to_string(filename).expect("Error reading file");
+impl RunConfig {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        max_instr: u32,
+        trace: bool,
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        init: &str,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
+    ) -> RiscResult<RunConfig> {
+        Ok(RunConfig {
+            max_instr,
+            trace,
+            strict,
+            init: InitPolicy::from_spec(init)?,
+            input_queue: input_queue.iter().map(|i| *i as i16).collect(),
+            devices,
+            convention: convention.map(Convention::from_spec).transpose()?,
+            stack: stack.map(Stack::from_spec).transpose()?,
+            protection: protection.map(Protection::from_spec).transpose()?,
+            arch: Archtype::from_name(arch)?,
+        })
+    }
 
-    let mut proc = Risc16::new(Archtype::IS0, 100000);
+    /// Run the program once, with the registers of `test` set after initialization.
+    fn run(
+        &self,
+        rom: &[(String, Args)],
+        labels: &HashMap<String, usize>,
+        source_map: &SourceMap,
+        test: &[(i32, i32)],
+    ) -> RiscResult<Risc16> {
+        let mut proc = Risc16::new(self.arch, self.max_instr, self.strict);
+        proc.devices = devices::build_devices(&self.devices, &self.input_queue)?;
+        proc.set_init(self.init);
+        proc.source_map = source_map.clone();
+        proc.trace = self.trace;
+        proc.convention = self.convention.clone();
+        proc.stack = self.stack.clone();
+        proc.protection = self.protection.clone();
+        for input in test {
+            proc.write_reg(input.0 as usize, input.1 as i16)?;
+        }
+        if let Err(e) = proc.execute(rom, labels) {
+            proc.fail(e);
+        }
+        Ok(proc)
+    }
 
-    let (rom, labels) = load_rom(content).unwrap();
-    println!("{:?}", rom);
-    println!("{:?}", labels);
-    match proc.execute(&rom, &labels) {
-        Ok(_res) => println!("Success !"),
-        Err(e) => {
-            writeln!(proc.buffer, "Error! {}", e).unwrap();
-            println!("Error! {}", e)
+    /// Run the program once as configured, then once from the random state of each seed.
+    /// Returns the first run and the seeds whose outcome differs from it.
+    fn seed_differences(
+        &self,
+        rom: &[(String, Args)],
+        labels: &HashMap<String, usize>,
+        source_map: &SourceMap,
+        test: &[(i32, i32)],
+        seeds: &[u64],
+    ) -> RiscResult<(Risc16, Vec<u64>)> {
+        let reference = self.run(rom, labels, source_map, test)?;
+        let mut differs = Vec::new();
+        for seed in seeds {
+            let seeded = RunConfig {
+                init: InitPolicy::Random(*seed),
+                ..self.clone()
+            };
+            let proc = seeded.run(rom, labels, source_map, test)?;
+            if !reference.same_outcome(&proc) {
+                differs.push(*seed);
+            }
         }
+        Ok((reference, differs))
     }
-    proc.display_state(true);
 }
 
 pub fn main_from_str(code: &str) -> String {
-    let mut proc = Risc16::new(Archtype::IS0, 100000);
+    let mut proc = Risc16::new(Archtype::IS0, 100000, false);
 
     let (rom, labels) = load_rom(code.to_string()).unwrap();
     println!("{:?}", rom);
     println!("{:?}", labels);
     match proc.execute(&rom, &labels) {
         Ok(_res) => println!("Success !"),
-        Err(e) => {
-            writeln!(proc.buffer, "Error! {}", e).unwrap();
-            // println!("Error! {}", e)
-        }
+        Err(e) => proc.fail(e),
     }
     proc.display_state(true);
     proc.buffer
 }
 
 #[pymodule]
-fn librisc16_rs(_py: Python, m: &PyModule) -> PyResult<()> {
-    #[pyfn(m, "run_from_str_py")]
+#[allow(clippy::too_many_arguments)]
+fn risc16_rs(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Risc16>()?;
+    m.add_class::<Fault>()?;
+    m.add("AssemblyError", py.get_type::<AssemblyError>())?;
+    m.add("ExecutionError", py.get_type::<ExecutionError>())?;
+    m.add(
+        "InstructionLimitError",
+        py.get_type::<InstructionLimitError>(),
+    )?;
+
+    /// Assemble and run `code` once.
+    ///
+    /// Returns the output buffer (trace, warnings, runtime error and console output) and the final state.
+    /// Raises AssemblyError if the code cannot be assembled.
+    #[pyfn(
+        m,
+        "run_from_str_py",
+        strict = "false",
+        input_queue = "Vec::new()",
+        devices = "None",
+        init = "\"zero\"",
+        convention = "None",
+        stack = "None",
+        protection = "None",
+        arch = "\"IS0\""
+    )]
+    #[text_signature = "(max_instr, trace, code, strict=False, input_queue=[], devices=None, init=\"zero\", convention=None, stack=None, protection=None, arch=\"IS0\")"]
     fn run_from_str_py(
-        _py: Python,
+        py: Python,
         max_instr: u32,
         trace: bool,
         code: &str,
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        init: &str,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
     ) -> PyResult<(String, String)> {
-        let mut proc = Risc16::new(Archtype::IS0, max_instr);
-        let (rom, labels) = match load_rom(code.to_string()) {
-            Ok((rom, labels)) => (rom, labels),
-            Err(e) => return Err(PyErr::from(e)),
-        };
-        match proc.execute(&rom, &labels) {
-            Ok(_res) => (), //println!("Success !"),
-            Err(e) => {
-                writeln!(proc.buffer, "Error! {}", e).unwrap();
-                // println!("Error! {}", e)
-            }
+        let proc = run_py(
+            py,
+            max_instr,
+            trace,
+            code,
+            strict,
+            input_queue,
+            devices,
+            init,
+            convention,
+            stack,
+            protection,
+            arch,
+        )?;
+        let mut buffer = proc.buffer.to_string();
+        let console = proc.console();
+        if !console.is_empty() {
+            writeln!(buffer, "Console:\n{}", console).unwrap();
         }
-        Ok((proc.buffer.to_string(), proc.print_state(false)?))
+        Ok((buffer, proc.print_state(false, NumberFormat::Hex)?))
     }
 
-    #[pyfn(m, "test_batch_py")]
+    /// Assemble and run `code` once.
+    ///
+    /// Returns the final Risc16 state, with `error` set if the run faulted.
+    /// Raises AssemblyError if the code cannot be assembled.
+    #[pyfn(
+        m,
+        "run_py",
+        strict = "false",
+        input_queue = "Vec::new()",
+        devices = "None",
+        init = "\"zero\"",
+        convention = "None",
+        stack = "None",
+        protection = "None",
+        arch = "\"IS0\""
+    )]
+    #[text_signature = "(max_instr, trace, code, strict=False, input_queue=[], devices=None, init=\"zero\", convention=None, stack=None, protection=None, arch=\"IS0\")"]
+    fn run_py(
+        _py: Python,
+        max_instr: u32,
+        trace: bool,
+        code: &str,
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        init: &str,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
+    ) -> PyResult<Risc16> {
+        let config = RunConfig::new(
+            max_instr,
+            trace,
+            strict,
+            input_queue,
+            devices,
+            init,
+            convention,
+            stack,
+            protection,
+            arch,
+        )?;
+        let (rom, labels, source_map) = assemble(code, strict, config.arch)?;
+        Ok(config.run(&rom, &labels, &source_map, &[])?)
+    }
+
+    /// Assemble `code` and run it once per test vector, sequentially.
+    ///
+    /// Each test vector is a list of (register, value) pairs set before running.
+    /// Returns the final Risc16 state of every run, with `error` set on the faulty ones.
+    #[pyfn(
+        m,
+        "test_batch_py",
+        strict = "false",
+        input_queue = "Vec::new()",
+        devices = "None",
+        init = "\"zero\"",
+        convention = "None",
+        stack = "None",
+        protection = "None",
+        arch = "\"IS0\""
+    )]
+    #[text_signature = "(max_instr, trace, code, tests, strict=False, input_queue=[], devices=None, init=\"zero\", convention=None, stack=None, protection=None, arch=\"IS0\")"]
     fn test_batch_py(
         _py: Python,
         max_instr: u32,
         trace: bool,
         code: &str,
         tests: Vec<Vec<(i32, i32)>>,
-    ) -> PyResult<Vec<[i16; 8]>> {
-        let mut proc = Risc16::new(Archtype::IS0, max_instr);
-        let (rom, labels) = match load_rom(code.to_string()) {
-            Ok((rom, labels)) => (rom, labels),
-            Err(e) => return Err(PyErr::from(e)),
-        };
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        init: &str,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
+    ) -> PyResult<Vec<Risc16>> {
+        let config = RunConfig::new(
+            max_instr,
+            trace,
+            strict,
+            input_queue,
+            devices,
+            init,
+            convention,
+            stack,
+            protection,
+            arch,
+        )?;
+        let (rom, labels, source_map) = assemble(code, strict, config.arch)?;
 
         let mut outputs = Vec::new();
         for test in tests {
-            proc.reset_state();
-            for input in test {
-                proc.registers[input.0 as usize] = input.1 as i16
-            }
-            match proc.execute(&rom, &labels) {
-                Ok(_res) => (), //println!("Success !"),
-                Err(e) => {
-                    writeln!(proc.buffer, "Error! {}", e).unwrap();
-                }
-            }
-            outputs.push(proc.registers)
+            outputs.push(config.run(&rom, &labels, &source_map, &test)?)
         }
         Ok(outputs)
     }
 
-    #[pyfn(m, "test_batch_par_py")]
+    /// Assemble `code` and run it once per test vector, in parallel.
+    ///
+    /// Each test vector is a list of (register, value) pairs set before running.
+    /// Returns the final Risc16 state of every run, with `error` set on the faulty ones.
+    #[pyfn(
+        m,
+        "test_batch_par_py",
+        strict = "false",
+        input_queue = "Vec::new()",
+        devices = "None",
+        init = "\"zero\"",
+        convention = "None",
+        stack = "None",
+        protection = "None",
+        arch = "\"IS0\""
+    )]
+    #[text_signature = "(max_instr, trace, code, tests, strict=False, input_queue=[], devices=None, init=\"zero\", convention=None, stack=None, protection=None, arch=\"IS0\")"]
     fn test_batch_par_py(
         py: Python,
         max_instr: u32,
         trace: bool,
         code: &str,
         tests: Vec<Vec<(i32, i32)>>,
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        init: &str,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
         // ) -> PyResult<Vec<[i16; 8]>> {
     ) -> PyResult<Vec<Risc16>> {
-        let (rom, labels) = match load_rom(code.to_string()) {
-            Ok((rom, labels)) => (rom, labels),
-            Err(e) => return Err(PyErr::from(e)),
-        };
+        let config = RunConfig::new(
+            max_instr,
+            trace,
+            strict,
+            input_queue,
+            devices,
+            init,
+            convention,
+            stack,
+            protection,
+            arch,
+        )?;
+        let (rom, labels, source_map) = assemble(code, strict, config.arch)?;
 
         py.allow_threads(|| {
             let outputs = tests
                 .par_iter()
-                .map(|test| {
-                    let mut proc = Risc16::new(Archtype::IS0, max_instr);
-                    for input in test {
-                        proc.registers[input.0 as usize] = input.1 as i16
-                    }
-                    match proc.execute(&rom, &labels) {
-                        Ok(_res) => (), //println!("Success !"),
-                        Err(e) => {
-                            writeln!(proc.buffer, "Error! {}", e).unwrap();
-                        }
-                    }
-                    // return proc.registers;
-                    return proc;
-                })
-                .collect::<Vec<_>>();
+                .map(|test| config.run(&rom, &labels, &source_map, test))
+                .collect::<RiscResult<Vec<_>>>()?;
             Ok(outputs)
         })
     }
 
+    /// Assemble `code` and run every test vector from zeroed state, then again
+    /// with random registers and memory for each seed.
+    ///
+    /// Returns for every test vector the zeroed run and the seeds whose outcome differs,
+    /// a non empty list hints at a read of an uninitialized register or memory word.
+    #[pyfn(
+        m,
+        "test_batch_seeds_py",
+        strict = "false",
+        input_queue = "Vec::new()",
+        devices = "None",
+        convention = "None",
+        stack = "None",
+        protection = "None",
+        arch = "\"IS0\""
+    )]
+    #[text_signature = "(max_instr, trace, code, tests, seeds, strict=False, input_queue=[], devices=None, convention=None, stack=None, protection=None, arch=\"IS0\")"]
+    fn test_batch_seeds_py(
+        py: Python,
+        max_instr: u32,
+        trace: bool,
+        code: &str,
+        tests: Vec<Vec<(i32, i32)>>,
+        seeds: Vec<u64>,
+        strict: bool,
+        input_queue: Vec<i32>,
+        devices: Option<Vec<(String, u16)>>,
+        convention: Option<&str>,
+        stack: Option<&str>,
+        protection: Option<&str>,
+        arch: &str,
+    ) -> PyResult<Vec<(Risc16, Vec<u64>)>> {
+        let config = RunConfig::new(
+            max_instr,
+            trace,
+            strict,
+            input_queue,
+            devices,
+            "zero",
+            convention,
+            stack,
+            protection,
+            arch,
+        )?;
+        let (rom, labels, source_map) = assemble(code, strict, config.arch)?;
+
+        py.allow_threads(|| {
+            let outputs = tests
+                .par_iter()
+                .map(|test| config.seed_differences(&rom, &labels, &source_map, test, &seeds))
+                .collect::<RiscResult<Vec<_>>>()?;
+            Ok(outputs)
+        })
+    }
+
+    /// Format `code`, keeping comments and blank lines and aligning labels, mnemonics,
+    /// operands and trailing comments in columns.
+    ///
+    /// Raises AssemblyError if the code cannot be assembled.
+    #[pyfn(m, "format_py")]
+    #[text_signature = "(code)"]
+    fn format_py(_py: Python, code: &str) -> PyResult<String> {
+        Ok(format::format_source(code)?)
+    }
+
+    /// Assemble `code` and return the decoded program, one instruction per line.
+    ///
+    /// Raises AssemblyError if the code cannot be assembled.
     #[pyfn(m, "load_rom_py")]
-    fn load_rom_py<'a>(_py: Python, code: &str) -> PyResult<String> {
+    #[text_signature = "(code)"]
+    fn load_rom_py(_py: Python, code: &str) -> PyResult<String> {
         match load_rom(code.to_string()) {
             Ok((rom, labels)) => {
-                let verified_code = format_code(rom, labels);
+                let verified_code = format_code(&rom, &labels);
                 Ok(verified_code.join("\n"))
             }
             Err(e) => Err(PyErr::from(e)),
@@ -733,3 +2339,740 @@ fn librisc16_rs(_py: Python, m: &PyModule) -> PyResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fault(err: CustomError) -> Fault {
+        match err {
+            CustomError::Fault(fault) => fault,
+            err => panic!("expected a fault, got: {}", err),
+        }
+    }
+
+    /// The fault raised while assembling `code`.
+    fn assembly_fault(code: &str) -> Fault {
+        match load_rom(code.to_string()) {
+            Ok(_) => panic!("{:?} assembled", code),
+            Err(err) => fault(err),
+        }
+    }
+
+    /// Run `code` until HALT or a fault, which is left in `error`.
+    fn run(code: &str, strict: bool) -> Risc16 {
+        let (rom, labels, source_map) = assemble(code, strict, Archtype::IS0).unwrap();
+        let mut proc = Risc16::new(Archtype::IS0, 10000, strict);
+        proc.source_map = source_map;
+        if let Err(err) = proc.execute(&rom, &labels) {
+            proc.fail(err);
+        }
+        proc
+    }
+
+    /// The fault raised while assembling `code` in strict mode.
+    fn strict_fault(code: &str) -> Fault {
+        match assemble(code, true, Archtype::IS0) {
+            Ok(_) => panic!("{:?} assembled in strict mode", code),
+            Err(err) => fault(err),
+        }
+    }
+
+    fn operands(code: &str) -> Args {
+        load_rom(code.to_string()).unwrap().0.remove(0).1
+    }
+
+    #[test]
+    fn register_names_and_aliases() {
+        assert!(matches!(operands("add r1,$2,3"), Args::A23(a) if a == [1, 2, 3]));
+        assert!(matches!(operands("jalr ra,zero"), Args::A23(a) if a == [7, 0]));
+        assert!(matches!(operands("add SP, R1, r2"), Args::A23(a) if a == [6, 1, 2]));
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let fault = assembly_fault("a: nop\nb: nop\na: halt");
+        assert_eq!(fault.kind, FaultKind::DuplicateLabel);
+        assert_eq!(fault.message, "Label a defined twice");
+        assert_eq!((fault.pc, fault.line), (2, Some(3)));
+    }
+
+    #[test]
+    fn offset_syntax() {
+        assert!(matches!(operands("lw 1, 4(sp)"), Args::A2i((1, 6, imm)) if imm == "4"));
+        assert!(matches!(operands("sw r2,(r3)"), Args::A2i((2, 3, imm)) if imm == "0"));
+        assert!(matches!(operands("lw 1,2,-3"), Args::A2i((1, 2, imm)) if imm == "-3"));
+        // only commas separate operands, whitespace stays inside them
+        assert!(matches!(operands("lw 1, 4 (2)"), Args::A2i((1, 2, imm)) if imm == "4"));
+        assert!(
+            matches!(operands("addi 1,1,data - 1"), Args::A2i((1, 1, imm)) if imm == "data - 1")
+        );
+        assert_eq!(
+            run("addi 1,0,data - 1\nhalt\ndata: .fill 0", false).registers[1],
+            1
+        );
+    }
+
+    #[test]
+    fn beq_label_expressions() {
+        // loop + 3 is the halt after the loop, in strict and lenient mode alike
+        let code = "movi 1,2\nloop: addi 1,1,-1\nbeq 1,0,loop+3\nbeq 0,0,loop\nhalt";
+        for strict in [false, true] {
+            let proc = run(code, strict);
+            assert!(proc.error.is_none());
+            assert_eq!((proc.registers[1], proc.instr_count), (0, 6));
+        }
+        // a number stays an offset
+        assert_eq!(run("beq 0,0,1\nmovi 1,1\nhalt", true).registers[1], 0);
+        let fault = strict_fault("nop\nbeq 0,0,end+70\nend: halt");
+        assert_eq!(
+            (fault.kind, fault.line),
+            (FaultKind::JumpOutOfRange, Some(2))
+        );
+    }
+
+    #[test]
+    fn runs_reset_the_devices() {
+        let code = "movi 3,0xfff1\nlw 1,3,0\nmovi 4,0xfff0\nmovi 2,72\nsw 2,4,0\nhalt";
+        let mut proc = Risc16::new(Archtype::IS0, 1000, false);
+        proc.devices = devices::default_devices(&[5]);
+        for _ in 0..2 {
+            proc.run(code).unwrap();
+            assert!(proc.error.is_none());
+            assert_eq!(proc.registers[1], 5);
+            assert_eq!(proc.devices[0].output(), Some("H"));
+        }
+        // a device failing to reset faults the run before its first instruction
+        Python::with_gil(|py| {
+            let locals = pyo3::types::PyDict::new(py);
+            let class = "class Device:\n    def reset(self):\n        raise OSError('unplugged')\n";
+            py.run(class, None, Some(locals)).unwrap();
+            let obj = py.eval("Device()", None, Some(locals)).unwrap();
+            proc.add_device_py(obj.into(), "disk".to_string(), 0x80, None);
+        });
+        proc.run(code).unwrap();
+        let error = proc.error.unwrap();
+        assert_eq!(error.kind, FaultKind::DeviceError);
+        assert_eq!(error.message, "Device disk failed: OSError: unplugged");
+        assert_eq!(proc.instr_count, 0);
+    }
+
+    #[test]
+    fn lenient_operand_errors() {
+        let lenient_fault = |code| fault(assemble(code, false, Archtype::IS0).unwrap_err());
+        let fault = lenient_fault("nop\nlw 1, 4 2");
+        assert_eq!(fault.message, "Wrong number of arguments");
+        assert_eq!(fault.line, Some(2));
+        let fault = lenient_fault("addi 1,1,1 2");
+        assert_eq!(fault.kind, FaultKind::BadOperand);
+        assert_eq!(fault.message, "Error processing label/imm");
+        assert_eq!(fault.line, Some(1));
+        assert_eq!(lenient_fault("sw 1,2,nowhere").kind, FaultKind::BadOperand);
+        // lenient mode still lets immediates overflow their field
+        assert!(assemble("addi 1,1,100", false, Archtype::IS0).is_ok());
+    }
+
+    #[test]
+    fn register_errors() {
+        let fault = assembly_fault("add 1,2,8");
+        assert_eq!(fault.kind, FaultKind::RegisterOutOfRange);
+        assert_eq!(fault.message, "Register out of range: 8");
+        assert_eq!(fault.line, Some(1));
+        let fault = assembly_fault("nop\nbeq 7,r9,end");
+        assert_eq!(fault.kind, FaultKind::RegisterOutOfRange);
+        assert_eq!((fault.pc, fault.line), (1, Some(2)));
+        let fault = assembly_fault("add 1,x,2");
+        assert_eq!(fault.kind, FaultKind::BadOperand);
+        assert_eq!(fault.message, "Bad register: x");
+        assert_eq!(assembly_fault("add 1,2").kind, FaultKind::BadOperand);
+        assert_eq!(
+            assembly_fault("lw 1,4(r8)").kind,
+            FaultKind::RegisterOutOfRange
+        );
+    }
+
+    #[test]
+    fn resolve_immediates() {
+        let mut labels = HashMap::new();
+        labels.insert("data".to_string(), 17);
+        let imm = |arg| resolve_imm(arg, &labels);
+        assert_eq!(imm("-64"), Some(-64));
+        assert_eq!(imm("0x3ff"), Some(1023));
+        assert_eq!(imm("0b101"), Some(5));
+        // no truncation to 16 bits before the range checks
+        assert_eq!(imm("65537"), Some(65537));
+        assert_eq!(imm("data+2"), Some(19));
+        assert_eq!(imm("data - 1 + 0x10"), Some(32));
+        assert_eq!(imm("hi(0xffc0)"), Some(1023));
+        assert_eq!(imm("lo(0xffc0)"), Some(0));
+        assert_eq!(imm("hi(0x1234)"), Some(0x48));
+        assert_eq!(imm("lo(0x1234)"), Some(0x34));
+        assert_eq!(imm("nowhere"), None);
+    }
+
+    #[test]
+    fn imm7_boundaries() {
+        let mut proc = Risc16::new(Archtype::IS0, 100, true);
+        assert_eq!(proc.fit_imm7(-64).ok(), Some(-64));
+        assert_eq!(proc.fit_imm7(63).ok(), Some(63));
+        for imm in &[-65, 64, 65537] {
+            let fault = fault(proc.fit_imm7(*imm).unwrap_err());
+            assert_eq!(fault.kind, FaultKind::ImmediateOutOfRange);
+        }
+        // lenient mode keeps the low 7 bits, as the hardware would
+        let mut proc = Risc16::new(Archtype::IS0, 100, false);
+        assert_eq!(proc.fit_imm7(64).ok(), Some(-64));
+        assert_eq!(proc.fit_imm7(-65).ok(), Some(63));
+        assert!(proc.buffer.contains("Immediate Too BIG : 64"));
+
+        assert!(assemble("addi 1,1,-64\naddi 1,1,63\nhalt", true, Archtype::IS0).is_ok());
+        for code in &["addi 1,1,-65", "addi 1,1,64", "addi 1,1,65537", "lw 1,2,64"] {
+            assert_eq!(strict_fault(code).kind, FaultKind::ImmediateOutOfRange);
+        }
+    }
+
+    #[test]
+    fn lui_boundaries() {
+        assert!(assemble("lui 1,0\nlui 1,1023\nhalt", true, Archtype::IS0).is_ok());
+        for code in &["lui 1,1024", "lui 1,-1", "lui 1,65537", "movi 1,65536"] {
+            assert_eq!(strict_fault(code).kind, FaultKind::ImmediateOutOfRange);
+        }
+        let proc = run("lui 1,0\nlui 2,1\nlui 3,1023\nlui 4,1024\nhalt", false);
+        assert_eq!(proc.registers[1..5], [0, 0x40, 0xffc0_u16 as i16, 0]);
+        let proc = run("lui 1,hi(0xffc0)\naddi 1,1,lo(0xffc0)\nhalt", true);
+        assert_eq!(proc.registers[1], 0xffc0_u16 as i16);
+        let proc = run("lui 1,hi(0x1234)\naddi 1,1,lo(0x1234)\nhalt", true);
+        assert_eq!(proc.registers[1], 0x1234);
+    }
+
+    /// A program with data words after its 5 instructions.
+    const DATA: &str = "lw 1,0,count\nmovi 2,table\nlw 3,2,1\nsw 3,0,count\nhalt
+count: .fill 3
+table: .fill -1
+       .fill count
+buf:   .space 2
+";
+
+    #[test]
+    fn data_directives() {
+        let (rom, labels, source_map) = assemble(DATA, true, Archtype::IS0).unwrap();
+        assert_eq!(rom.len(), 5);
+        assert_eq!((labels["count"], labels["table"], labels["buf"]), (5, 6, 8));
+        assert_eq!(source_map.data, [3, -1, 5, 0, 0]);
+        let proc = run(DATA, true);
+        assert!(proc.error.is_none());
+        assert_eq!(proc.registers[1..4], [3, 6, 5]);
+        assert!(proc.uninit_reads.is_empty());
+
+        let fault = assembly_fault("halt\n.fill nowhere");
+        assert_eq!((fault.kind, fault.line), (FaultKind::BadOperand, Some(2)));
+        assert_eq!(fault.message, "Bad data word: nowhere");
+        assert_eq!(
+            assembly_fault(".fill 1,2").message,
+            ".fill takes one operand"
+        );
+        assert_eq!(assembly_fault(".space -1").message, "Bad word count: -1");
+        assert_eq!(
+            assembly_fault(".word 1").kind,
+            FaultKind::UnknownInstruction
+        );
+        let fault = assembly_fault("halt\n.space 255\n.fill 1");
+        assert_eq!(fault.line, Some(3));
+        assert_eq!(
+            fault.message,
+            "The program data runs past the 256 words of RAM, from 0x0001 to 0x0100"
+        );
+    }
+
+    #[test]
+    fn dump_memory_labels() {
+        let proc = run(DATA, false);
+        assert_eq!(
+            proc.dump_memory(0, 10, &[NumberFormat::Signed]),
+            format!(
+                "0x0000: {}      5     -1      5  count=0x0005 table=0x0006\n\
+                 0x0008:       0      0{:42}  buf=0x0008\n",
+                "      0".repeat(5),
+                ""
+            )
+        );
+        // code labels are ROM indices, not addresses
+        assert!(proc.data_labels(0..5).is_empty());
+    }
+
+    #[test]
+    fn memory_diff_labels() {
+        let proc = run(&format!("movi 4,7\nsw 4,0,0\n{}", DATA), false);
+        // the data follows the 7 instructions: count is at 7, overwritten with its address
+        assert_eq!(
+            proc.memory_diff(),
+            [(0, None, 0, 7), (7, Some("count".to_string()), 3, 7)]
+        );
+        assert_eq!(
+            proc.format_memory_diff(),
+            "Memory changes:\n  0x0000: 0x0000 (0, 0) -> 0x0007 (7, 7)\n  \
+             0x0007 (count): 0x0003 (3, 3) -> 0x0007 (7, 7)\n"
+        );
+    }
+
+    #[test]
+    fn stack_checks() {
+        let stack = Some("top=0x7f;limit=0x78");
+        let config = RunConfig::new(
+            1000,
+            false,
+            false,
+            vec![],
+            None,
+            "zero",
+            None,
+            stack,
+            None,
+            "IS0",
+        )
+        .unwrap();
+        let run = |code: &str| {
+            let (rom, labels, source_map) = assemble(code, false, Archtype::IS0).unwrap();
+            config.run(&rom, &labels, &source_map, &[]).unwrap().error
+        };
+        // a copy of the stack pointer is checked like the stack pointer
+        let code = "movi 6,0x80\nadd 5,6,0\naddi 4,5,-8\nsw 1,5,-1\nsw 1,4,-1\nhalt";
+        let error = run(code).unwrap();
+        assert_eq!(error.kind, FaultKind::StackOverflow { addr: 0x77 });
+        assert_eq!(error.pc, 4);
+        // until it is overwritten
+        assert!(run("movi 6,0x80\nadd 5,6,0\nmovi 5,0x10\nsw 1,5,0\nhalt").is_none());
+        assert!(run("movi 1,0x10\nadd 5,1,0\nsw 1,5,0\nhalt").is_none());
+        // data words come from the .fill and .space directives
+        let error = run("movi 6,buf+2\nsw 1,6,-1\nhalt\nbuf: .space 2").unwrap();
+        assert_eq!(error.kind, FaultKind::StackCollision { addr: 4 });
+        assert_eq!(
+            error.message,
+            "Stack write to 0x0004 collides with program data"
+        );
+    }
+
+    #[test]
+    fn spec_settings_and_conventions() {
+        let settings: Vec<_> = spec_settings(" link = r7 ;; code;args=").collect();
+        assert_eq!(
+            settings,
+            [("link", Some("r7")), ("code", None), ("args", Some(""))]
+        );
+        assert_eq!(spec_settings(" ").count(), 0);
+
+        let convention = Convention::from_spec("sp=r5; args=r1-r2,r4").unwrap();
+        assert_eq!((convention.link, convention.stack_pointer), (7, 5));
+        assert_eq!(
+            (convention.arguments, convention.callee_saved),
+            (vec![1, 2, 4], vec![4, 5])
+        );
+        assert_eq!(
+            Convention::from_spec("default").unwrap(),
+            Convention::default()
+        );
+        let error = |spec| Convention::from_spec(spec).unwrap_err().to_string();
+        assert_eq!(error("link"), "Bad calling convention setting: link");
+        assert_eq!(error("ra=r7"), "Unknown calling convention setting: ra");
+        assert_eq!(error("link=r8"), "Register out of range: r8");
+    }
+
+    #[test]
+    fn stack_specs() {
+        let stack = Stack::from_spec(" sp=r5 ; limit=0x40").unwrap();
+        assert_eq!(
+            (stack.stack_pointer, stack.top, stack.limit),
+            (5, 0xff, 0x40)
+        );
+        assert_eq!(Stack::from_spec("default").unwrap(), Stack::default());
+        let error = |spec| Stack::from_spec(spec).unwrap_err().to_string();
+        assert_eq!(error("top"), "Bad stack setting: top");
+        assert_eq!(error("bottom=0"), "Unknown stack setting: bottom");
+        assert_eq!(error("top=0x10000"), "Bad address: 0x10000");
+        assert_eq!(
+            error("top=0x10;limit=0x20"),
+            "Stack limit 0x0020 above its top 0x0010"
+        );
+    }
+
+    #[test]
+    fn protection_specs() {
+        use protection::Access;
+        let protection =
+            Protection::from_spec("code; readonly=0x00-0x0f,0x20 ;noaccess=0xff").unwrap();
+        assert!(protection.code);
+        assert_eq!(
+            protection.regions,
+            [
+                (0x00..=0x0f, Access::ReadOnly),
+                (0x20..=0x20, Access::ReadOnly),
+                (0xff..=0xff, Access::NoAccess)
+            ]
+        );
+        assert_eq!(Protection::from_spec("").unwrap(), Protection::default());
+        let error = |spec| Protection::from_spec(spec).unwrap_err().to_string();
+        assert_eq!(error("readonly"), "Bad memory protection setting: readonly");
+        assert_eq!(error("code=1"), "Unknown memory protection setting: code");
+        assert_eq!(
+            error("guard=0x10"),
+            "Unknown memory protection setting: guard"
+        );
+    }
+
+    #[test]
+    fn seed_differences() {
+        let config = RunConfig::new(
+            100,
+            false,
+            false,
+            vec![],
+            None,
+            "zero",
+            None,
+            None,
+            None,
+            "IS0",
+        )
+        .unwrap();
+        let differs = |code: &str, test: &[(i32, i32)]| {
+            let (rom, labels, source_map) = assemble(code, false, Archtype::IS0).unwrap();
+            let seeds = [1, 2, 3];
+            let (reference, differs) = config
+                .seed_differences(&rom, &labels, &source_map, test, &seeds)
+                .unwrap();
+            assert_eq!(reference.init, InitPolicy::Zero);
+            differs
+        };
+        // r3 is used as scratch, its initial value does not matter
+        assert!(differs("movi 3,9\nadd 1,3,0\nhalt", &[]).is_empty());
+        // r2 is read before being written, unless the test vector sets it
+        assert_eq!(differs("add 1,2,0\nhalt", &[]), [1, 2, 3]);
+        assert!(differs("add 1,2,0\nhalt", &[(2, 5)]).is_empty());
+        // a random memory word sends the program into a loop or not
+        let code = "lw 1,0,50\nbeq 1,0,end\nloop: beq 0,0,loop\nend: halt";
+        assert_eq!(differs(code, &[]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn same_outcome() {
+        let proc = |code| run(code, false);
+        let halt = proc("movi 1,3\nhalt");
+        assert!(halt.same_outcome(&proc("movi 1,3\nnop\nhalt")));
+        assert!(!halt.same_outcome(&proc("movi 1,4\nhalt")));
+        // registers left as they started are not compared
+        let mut seeded = proc("movi 1,3\nhalt");
+        seeded.registers[5] = 7;
+        seeded.initial_registers[5] = 7;
+        assert!(halt.same_outcome(&seeded));
+        // nor is the fault message, only its kind
+        let fault = proc("movi 1,3\nlw 2,1,-70");
+        assert!(!halt.same_outcome(&fault));
+        assert!(fault.same_outcome(&proc("movi 1,3\nnop\nlw 2,1,-70")));
+    }
+
+    #[test]
+    fn run_config_arch() {
+        let config = |arch| {
+            RunConfig::new(
+                1000,
+                false,
+                false,
+                vec![],
+                None,
+                "zero",
+                None,
+                None,
+                None,
+                arch,
+            )
+        };
+        let code = "movi 1,5\nhalt";
+        let (rom, labels, source_map) = assemble(code, false, Archtype::IS1).unwrap();
+        let proc = config("IS1")
+            .unwrap()
+            .run(&rom, &labels, &source_map, &[])
+            .unwrap();
+        assert_eq!((proc.arch, proc.registers[1]), (Archtype::IS1, 5));
+        let err = config("IS3").err().unwrap();
+        assert_eq!(err.to_string(), "Unknown architecture: IS3");
+    }
+
+    #[test]
+    fn code_region() {
+        let config = |spec| {
+            RunConfig::new(
+                1000,
+                false,
+                false,
+                vec![],
+                None,
+                "zero",
+                None,
+                None,
+                Some(spec),
+                "IS0",
+            )
+            .unwrap()
+        };
+        let code = "movi 1,data\nlw 2,1,0\nsw 2,0,{}\nhalt\ndata: .fill 9";
+        let run = |spec, addr| {
+            let (rom, labels, source_map) =
+                assemble(&code.replace("{}", addr), false, Archtype::IS0).unwrap();
+            config(spec).run(&rom, &labels, &source_map, &[]).unwrap()
+        };
+        // the data after the code stays usable
+        let proc = run("code", "5");
+        assert!(proc.error.is_none());
+        assert_eq!((proc.registers[2], proc.ram[5]), (9, 9));
+        let error = run("code", "3").error.unwrap();
+        assert_eq!(error.kind, FaultKind::AccessViolation { addr: 3 });
+        assert_eq!(
+            error.message,
+            "Access violation: write to 0x0003, in the code region"
+        );
+        assert_eq!((error.pc, error.line), (2, Some(3)));
+        // without the code setting the words of the code are plain memory
+        assert!(run("readonly=4", "3").error.is_none());
+        let error = run("readonly=4", "4").error.unwrap();
+        assert_eq!(error.kind, FaultKind::WriteProtected { addr: 4 });
+    }
+
+    #[test]
+    fn dump_memory_wraps_around() {
+        let proc = run("movi 1,0x1234\nsw 1,0,1\nhalt", false);
+        assert_eq!(
+            proc.dump_memory(0xfffc, 8, &[NumberFormat::Hex]),
+            "0xfffc:  ------ ------ ------ ------ 0x0000 0x1234 0x0000 0x0000\n"
+        );
+        assert_eq!(
+            proc.dump_memory(0xfffe, 3, &[NumberFormat::Signed]),
+            "0xfffe:  ------ ------      0\n"
+        );
+    }
+
+    #[test]
+    fn pc_wraps_to_16_bits() {
+        // lenient mode: a jump before instruction 0 lands at the top of the 16 bits space
+        let proc = run("beq 0,0,-5\nhalt", false);
+        let error = proc.error.unwrap();
+        assert_eq!(error.kind, FaultKind::EndOfRom);
+        assert_eq!((proc.pc, error.pc), (65532, 65532));
+        // jumping to 0xffff runs into instruction 0 again
+        let proc = run("beq 0,0,-1\nhalt", false);
+        let error = proc.error.unwrap();
+        assert_eq!((error.kind, error.pc), (FaultKind::InstrLimit, 0));
+        assert_eq!(proc.instr_count, 10000);
+    }
+
+    #[test]
+    fn interrupt_configuration() {
+        let mut proc = Risc16::new(Archtype::IS1, 100, false);
+        assert!(proc.schedule_interrupt(0, 5).is_err());
+        assert!(proc.schedule_interrupt(8, 5).is_err());
+        assert!(proc.schedule_interrupt(7, 5).is_ok());
+        assert!(proc.set_timer_interrupt(Some(0)).is_err());
+        assert!(proc.set_timer_interrupt(Some(3)).is_ok());
+
+        let code = "nop\nreti\nhalt";
+        let fault = fault(assemble(code, false, Archtype::IS0).unwrap_err());
+        assert_eq!(fault.kind, FaultKind::UnknownInstruction);
+        assert_eq!((fault.pc, fault.line), (1, Some(2)));
+        assert!(assemble(code, false, Archtype::IS1).is_ok());
+        assert!(assemble(code, false, Archtype::IS2).is_ok());
+    }
+
+    #[test]
+    fn snapshot_resumes_the_run() {
+        let code = "movi 1,5\nlw 2,1,0\nadd 3,4,0\nsw 3,1,1\nmovi 2,0x200\nlw 1,2,0\nhalt";
+        let (rom, labels, source_map) = assemble(code, false, Archtype::IS1).unwrap();
+        let mut proc = Risc16::new(Archtype::IS1, 100, false);
+        proc.source_map = source_map;
+        proc.labels = labels;
+        proc.set_interrupt_handler("0".to_string());
+        proc.set_timer_interrupt(Some(50)).unwrap();
+        proc.prepare_run();
+        for _ in 0..2 {
+            proc.step(&rom).unwrap();
+        }
+        let mut restored = Risc16::from_json(&proc.to_json().unwrap()).unwrap();
+        assert_eq!(restored.interrupts.timer_period, Some(50));
+        assert_eq!(restored.interrupts.handler.as_deref(), Some("0"));
+        for proc in [&mut proc, &mut restored].iter_mut() {
+            while let Ok(true) = proc.step(&rom).map_err(|err| proc.fail(err)) {}
+        }
+        assert_eq!(restored.uninit_reads, proc.uninit_reads);
+        assert_eq!(restored.uninit_reads.len(), 2);
+        assert_eq!(restored.buffer, proc.buffer);
+        assert_eq!(restored.memory_diff(), proc.memory_diff());
+        let (error, original) = (restored.error.unwrap(), proc.error.unwrap());
+        assert_eq!(error.kind, FaultKind::MemoryOutOfBounds { addr: 0x200 });
+        assert_eq!((error.pc, error.line), (original.pc, original.line));
+        assert_eq!(error.line, Some(6));
+    }
+
+    /// Run tests/<name>.txt with the default devices, after `setup` configured the processor.
+    fn fixture(name: &str, arch: Archtype, setup: impl FnOnce(&mut Risc16)) -> Risc16 {
+        let path = format!("{}/tests/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        let (rom, labels, source_map) = assemble_file(&path, false, arch).unwrap();
+        let mut proc = Risc16::new(arch, 10000, false);
+        proc.devices = devices::default_devices(&[]);
+        proc.source_map = source_map;
+        setup(&mut proc);
+        if let Err(err) = proc.execute(&rom, &labels) {
+            proc.fail(err);
+        }
+        proc
+    }
+
+    #[test]
+    fn console_fixture() {
+        let proc = fixture("console", Archtype::IS0, |proc| {
+            proc.devices = devices::default_devices(&[72, 105]);
+        });
+        assert!(proc.error.is_none());
+        assert_eq!(proc.devices[0].output(), Some("Hi"));
+        // the input queue ends with -1, then the timer counted the 15 instructions before
+        assert_eq!((proc.registers[3], proc.registers[5]), (-1, 15));
+    }
+
+    #[test]
+    fn interrupts_fixture() {
+        let proc = fixture("interrupts", Archtype::IS1, |proc| {
+            proc.interrupts.handler = Some("isr".to_string());
+            proc.interrupts.timer_period = Some(50);
+            proc.interrupts.schedule.push((20, 3));
+        });
+        assert!(proc.error.is_none());
+        // one external interrupt, and a timer one every 50 of the 335 instructions
+        assert_eq!((proc.registers[1], proc.registers[6]), (0, 1));
+        assert_eq!((proc.registers[7], proc.instr_count), (6, 335));
+        // without a handler interrupts are ignored
+        let proc = fixture("interrupts", Archtype::IS1, |proc| {
+            proc.interrupts.timer_period = Some(50);
+        });
+        assert_eq!((proc.registers[6], proc.registers[7]), (0, 0));
+    }
+
+    #[test]
+    fn uninit_fixture() {
+        let proc = fixture("uninit", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        let reads: Vec<(usize, &str)> = proc
+            .uninit_reads
+            .iter()
+            .map(|(pc, _, location)| (*pc, location.as_str()))
+            .collect();
+        assert_eq!(reads, [(1, "r2"), (3, "mem[5]"), (4, "r4")]);
+        let error = fixture("uninit", Archtype::IS0, |proc| proc.strict = true)
+            .error
+            .unwrap();
+        assert_eq!(error.kind, FaultKind::UninitializedRead);
+        assert_eq!((error.pc, error.line), (1, Some(5)));
+    }
+
+    #[test]
+    fn stack_fixture() {
+        let proc = fixture("stack", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        assert_eq!(proc.registers[4], 15);
+        let proc = fixture("stack", Archtype::IS0, |proc| {
+            proc.stack = Some(Stack::from_spec("top=0x7f;limit=0x78").unwrap());
+        });
+        let error = proc.error.unwrap();
+        assert_eq!(error.kind, FaultKind::StackOverflow { addr: 0x77 });
+        assert_eq!((error.pc, error.line), (8, Some(14)));
+        assert_eq!(proc.registers[1], 1);
+    }
+
+    #[test]
+    fn protection_fixture() {
+        let input = |proc: &mut Risc16| {
+            proc.ram[..4].copy_from_slice(&[1, 2, 3, 4]);
+            proc.ram_written[..4].copy_from_slice(&[true; 4]);
+        };
+        let proc = fixture("protection", Archtype::IS0, input);
+        assert!(proc.error.is_none());
+        assert_eq!((proc.registers[2], proc.ram[0]), (10, 10));
+        let proc = fixture("protection", Archtype::IS0, |proc| {
+            input(proc);
+            let spec = "readonly=0x00-0x03;noaccess=0xf0-0xff";
+            proc.protection = Some(Protection::from_spec(spec).unwrap());
+        });
+        let error = proc.error.unwrap();
+        assert_eq!(error.kind, FaultKind::WriteProtected { addr: 0 });
+        assert_eq!((error.pc, error.line), (10, Some(15)));
+        assert_eq!(proc.ram[0], 1);
+    }
+
+    #[test]
+    fn convention_fixture() {
+        let proc = fixture("convention", Archtype::IS0, |proc| {
+            proc.convention = Some(Convention::from_spec("default").unwrap());
+        });
+        assert!(proc.error.is_none());
+        let pcs: Vec<usize> = proc
+            .convention_violations
+            .iter()
+            .map(|(pc, _)| *pc)
+            .collect();
+        assert_eq!(pcs, [19, 19, 8]);
+        assert!(proc.convention_violations[2].1.contains("r5 instead of r7"));
+        // good doubled r1 and gave r4 back, bad clobbered r4 and left a word on the stack
+        assert_eq!(proc.registers[1], 10);
+        assert_eq!((proc.registers[4], proc.registers[6]), (20, 0x7f));
+    }
+
+    #[test]
+    fn calls_fixture() {
+        let proc = fixture("calls", Archtype::IS0, |_| ());
+        assert!(proc
+            .buffer
+            .contains("WARNING, mismatched return to instruction 7, expected 3"));
+        let error = proc.error.as_ref().unwrap();
+        assert_eq!(error.kind, FaultKind::MemoryOutOfBounds { addr: 300 });
+        assert_eq!((error.pc, error.line), (14, Some(23)));
+        let backtrace = [("oob", 14), ("outer", 11), ("back", 8)];
+        let backtrace: Vec<(String, usize)> = backtrace
+            .iter()
+            .map(|(name, pc)| (name.to_string(), *pc))
+            .collect();
+        assert_eq!(proc.backtrace(), backtrace);
+    }
+
+    #[test]
+    fn arithmetic_fixtures() {
+        let proc = fixture("lui", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        let expected = [0x0000, 0x0040, 0xffc0, 0x7fc0, 0x8000, 0x0040, 0x1234];
+        assert_eq!(proc.registers[1..], expected.map(|r: u16| r as i16));
+        // 0x7fff * 7 = 0x3_7ff9, high word in r4 and low word in r3
+        let proc = fixture("mul", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        assert_eq!((proc.registers[4], proc.registers[3]), (3, 0x7ff9));
+        let proc = fixture("mul_2_noinit", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        let pcs: Vec<usize> = proc.uninit_reads.iter().map(|(pc, _, _)| *pc).collect();
+        assert_eq!(pcs, [14, 16]);
+        // 0x7fff + 0x7fff with the carry in r4
+        let proc = fixture("17add", Archtype::IS0, |_| ());
+        assert!(proc.error.is_none());
+        assert_eq!((proc.registers[3], proc.registers[4]), (-2, 1));
+    }
+
+    #[test]
+    fn faulty_fixtures() {
+        let error = fixture("inf_loop", Archtype::IS0, |_| ()).error.unwrap();
+        assert_eq!(error.kind, FaultKind::InstrLimit);
+        assert_eq!((error.pc, error.line), (2, Some(3)));
+        // 0x42 does not fit the 7 bits of addi, and the jump to fini is too long for beq:
+        // both are truncated, the jump lands far before the program
+        let proc = fixture("bit_extract", Archtype::IS0, |_| ());
+        assert_eq!(proc.error.unwrap().kind, FaultKind::EndOfRom);
+        assert_eq!(proc.registers[1], -62);
+        assert!(proc.buffer.contains("Jump too long"));
+        let path = format!("{}/tests/bit_extract.txt", env!("CARGO_MANIFEST_DIR"));
+        let error = fault(assemble_file(&path, true, Archtype::IS0).unwrap_err());
+        assert_eq!(error.kind, FaultKind::ImmediateOutOfRange);
+        assert_eq!(error.line, Some(2));
+    }
+}
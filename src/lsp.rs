@@ -0,0 +1,585 @@
+use crate::dap::{read_message, write_message};
+use crate::format::format_source;
+use crate::syntax::{Span, SyntaxTree, TokenKind};
+use crate::{
+    check_instr, fit_word, process_statement, resolve_imm, Assembled, CustomError, FaultKind,
+    RAM_SIZE,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+const ERROR: i64 = 1;
+const WARNING: i64 = 2;
+const METHOD_NOT_FOUND: i64 = -32601;
+const KEYWORD_ITEM: i64 = 14;
+const REFERENCE_ITEM: i64 = 18;
+
+const DOCUMENT_METHODS: &[&str] = &[
+    "textDocument/hover",
+    "textDocument/definition",
+    "textDocument/references",
+    "textDocument/completion",
+    "textDocument/formatting",
+];
+
+/// Syntax and semantics of each mnemonic, shown on hover and completion.
+const MNEMONICS: &[(&str, &str)] = &[
+    ("add", "add rA, rB, rC\n\nrA = rB + rC"),
+    ("addi", "addi rA, rB, imm\n\nrA = rB + imm, imm is 7 bits signed (-64 to 63)"),
+    ("nand", "nand rA, rB, rC\n\nrA = ~(rB & rC)"),
+    ("lui", "lui rA, imm\n\nrA = imm << 6, imm is 10 bits unsigned (0 to 1023)"),
+    (
+        "lw",
+        "lw rA, rB, imm  or  lw rA, imm(rB)\n\nrA = mem[rB + imm], imm is 7 bits signed (-64 to 63)",
+    ),
+    (
+        "sw",
+        "sw rA, rB, imm  or  sw rA, imm(rB)\n\nmem[rB + imm] = rA, imm is 7 bits signed (-64 to 63)",
+    ),
+    (
+        "beq",
+        "beq rA, rB, label\n\nif rA == rB, jump to label: pc = pc + 1 + offset, \
+         the offset is 7 bits signed (-64 to 63)",
+    ),
+    ("jalr", "jalr rA, rB\n\nrA = pc + 1, then pc = rB"),
+    (
+        "movi",
+        "movi rA, imm\n\nrA = imm, any 16 bits value (pseudo instruction: lui then addi)",
+    ),
+    ("nop", "nop\n\nDo nothing"),
+    ("halt", "halt\n\nStop the program"),
+    ("reset", "reset\n\nJump back to the first instruction"),
+    ("reti", "reti\n\nReturn from an interrupt handler (IS1 and IS2 only)"),
+    (
+        ".fill",
+        ".fill value\n\nOne data word, a number or a label, laid out in RAM after the code",
+    ),
+    (".space", ".space count\n\ncount data words set to 0, laid out in RAM after the code"),
+];
+
+/// Syntax tree, labels and diagnostics of one document.
+struct Analysis<'a> {
+    tree: SyntaxTree<'a>,
+    /// Name span and value of each label definition: a ROM index, or a RAM address for
+    /// the labels of data words.
+    labels: HashMap<&'a str, (Span, usize)>,
+    /// RAM addresses of the data words, which follow the code.
+    data: Range<usize>,
+    diagnostics: Vec<Value>,
+}
+
+fn analyze(text: &str) -> Analysis<'_> {
+    let tree = SyntaxTree::parse(text);
+    let mut labels = HashMap::new();
+    let mut diagnostics = Vec::new();
+    let mut rom = Vec::new();
+    let mut spans = Vec::new();
+    // data words with the span of their directive
+    let mut data = Vec::new();
+    // labels name the next statement, data labels get their address once the code is done
+    let mut pending = Vec::new();
+    let mut data_labels = Vec::new();
+    for line in &tree.lines {
+        for label in &line.labels {
+            let name = tree.text(label);
+            if labels.contains_key(name) {
+                // the first definition stands, as when assembling
+                let msg = format!("Label {} defined twice", name);
+                diagnostics.push(diagnostic(&tree, label, ERROR, &msg));
+                continue;
+            }
+            labels.insert(name, (label.clone(), 0));
+            pending.push(name);
+        }
+        // every statement is assembled on its own so that all errors are reported, not only the first
+        if let Some(statement) = &line.statement {
+            match process_statement(&tree, statement) {
+                Ok(Assembled::Instr(instr)) => {
+                    for name in pending.drain(..) {
+                        labels.get_mut(name).unwrap().1 = rom.len();
+                    }
+                    rom.push(instr);
+                    spans.push(statement.span.clone());
+                }
+                Ok(Assembled::Data(words)) => {
+                    data_labels.extend(pending.drain(..).map(|name| (name, data.len())));
+                    data.extend(words.into_iter().map(|word| (word, statement.span.clone())));
+                }
+                Err(e) => diagnostics.push(diagnostic(&tree, &statement.span, ERROR, &message(&e))),
+            }
+        }
+    }
+    for name in pending {
+        labels.get_mut(name).unwrap().1 = rom.len();
+    }
+    for (name, offset) in data_labels {
+        labels.get_mut(name).unwrap().1 = rom.len() + offset;
+    }
+    let indices = labels
+        .iter()
+        .map(|(label, (_, index))| (label.to_string(), *index))
+        .collect();
+    for (pc, (instr, args)) in rom.iter().enumerate() {
+        if let Err(e) = check_instr(pc, instr, args, &indices, true) {
+            // out of range values only fault in strict mode
+            let severity = match &e {
+                CustomError::Fault(fault)
+                    if matches!(
+                        fault.kind,
+                        FaultKind::ImmediateOutOfRange | FaultKind::JumpOutOfRange
+                    ) =>
+                {
+                    WARNING
+                }
+                _ => ERROR,
+            };
+            diagnostics.push(diagnostic(&tree, &spans[pc], severity, &message(&e)));
+        }
+    }
+    for (addr, (word, span)) in (rom.len()..).zip(&data) {
+        let msg = if addr >= RAM_SIZE {
+            format!(
+                "Data word at {:#06x}, past the {} words of RAM",
+                addr, RAM_SIZE
+            )
+        } else if resolve_imm(word, &indices).and_then(fit_word).is_none() {
+            format!("Bad data word: {}", word)
+        } else {
+            continue;
+        };
+        diagnostics.push(diagnostic(&tree, span, ERROR, &msg));
+    }
+    Analysis {
+        tree,
+        labels,
+        data: rom.len()..rom.len() + data.len(),
+        diagnostics,
+    }
+}
+
+fn message(err: &CustomError) -> String {
+    match err {
+        CustomError::Fault(fault) => fault.message.clone(),
+        err => err.to_string(),
+    }
+}
+
+fn diagnostic(tree: &SyntaxTree, span: &Span, severity: i64, message: &str) -> Value {
+    json!({
+        "range": range(tree, span),
+        "severity": severity,
+        "source": "risc16",
+        "message": message,
+    })
+}
+
+fn range(tree: &SyntaxTree, span: &Span) -> Value {
+    let (start_line, start) = tree.position(span.start);
+    let (end_line, end) = tree.position(span.end);
+    json!({
+        "start": {"line": start_line, "character": start},
+        "end": {"line": end_line, "character": end},
+    })
+}
+
+/// The word under the cursor, or just before it.
+fn word_at<'a>(tree: &SyntaxTree<'a>, position: &Value) -> Option<&'a str> {
+    let line = position["line"].as_u64()? as usize;
+    let offset = tree.offset(line, position["character"].as_u64()? as usize)?;
+    let before = offset.checked_sub(1).and_then(|o| tree.token_at(o));
+    [tree.token_at(offset), before]
+        .iter()
+        .flatten()
+        .find(|token| token.kind == TokenKind::Word)
+        .map(|token| tree.text(&token.span))
+}
+
+/// Language server for RiSC-16 assembly, editors talk to it over stdin and stdout.
+pub(crate) struct LspServer {
+    documents: HashMap<String, String>,
+}
+
+impl LspServer {
+    pub(crate) fn new() -> LspServer {
+        LspServer {
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Answer messages until `exit` or the end of the input.
+    pub(crate) fn serve(
+        &mut self,
+        mut input: impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or("");
+            let params = &message["params"];
+            if message.get("id").is_some() && !method.is_empty() {
+                let response = match self.request(method, params) {
+                    Some(result) => {
+                        json!({"jsonrpc": "2.0", "id": message["id"], "result": result})
+                    }
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unsupported method: {}", method),
+                        },
+                    }),
+                };
+                write_message(out, &response)?;
+                continue;
+            }
+            let uri = params["textDocument"]["uri"]
+                .as_str()
+                .unwrap_or("")
+                .to_string();
+            match method {
+                "exit" => return Ok(()),
+                "textDocument/didOpen" => {
+                    let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                "textDocument/didChange" => {
+                    // full document synchronization, the last change holds the whole text
+                    let changes = params["contentChanges"].as_array();
+                    if let Some(text) = changes
+                        .and_then(|c| c.last())
+                        .and_then(|c| c["text"].as_str())
+                    {
+                        self.documents.insert(uri.clone(), text.to_string());
+                    }
+                }
+                "textDocument/didClose" => {
+                    self.documents.remove(&uri);
+                }
+                _ => continue,
+            }
+            let diagnostics = match self.documents.get(&uri) {
+                Some(text) => analyze(text).diagnostics,
+                None => Vec::new(),
+            };
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": {"uri": uri, "diagnostics": diagnostics},
+            });
+            write_message(out, &notification)?;
+        }
+        Ok(())
+    }
+
+    /// Result of a request, None if the method is not supported.
+    fn request(&self, method: &str, params: &Value) -> Option<Value> {
+        if method == "initialize" {
+            return Some(json!({
+                "capabilities": {
+                    "positionEncoding": "utf-16",
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": {},
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": {"name": "risc16"},
+            }));
+        } else if method == "shutdown" {
+            return Some(Value::Null);
+        } else if !DOCUMENT_METHODS.contains(&method) {
+            return None;
+        }
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return Some(Value::Null),
+        };
+        let position = &params["position"];
+        let result = match method {
+            "textDocument/hover" => self.hover(text, position),
+            "textDocument/definition" => self.definition(uri, text, position),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"].as_bool();
+                self.references(uri, text, position, declaration.unwrap_or(true))
+            }
+            "textDocument/completion" => Some(self.completion(text)),
+            "textDocument/formatting" => self.formatting(text),
+            _ => return None,
+        };
+        Some(result.unwrap_or(Value::Null))
+    }
+
+    fn hover(&self, text: &str, position: &Value) -> Option<Value> {
+        let analysis = analyze(text);
+        let word = word_at(&analysis.tree, position)?;
+        let contents = match MNEMONICS.iter().find(|(name, _)| *name == word) {
+            Some((_, doc)) => doc.to_string(),
+            None => {
+                let (span, index) = analysis.labels.get(word)?;
+                let (line, _) = analysis.tree.position(span.start);
+                if analysis.data.contains(index) {
+                    format!("{}: data word at {:#06x}, line {}", word, index, line + 1)
+                } else {
+                    format!("{}: instruction {}, line {}", word, index, line + 1)
+                }
+            }
+        };
+        Some(json!({"contents": {"kind": "plaintext", "value": contents}}))
+    }
+
+    fn definition(&self, uri: &str, text: &str, position: &Value) -> Option<Value> {
+        let analysis = analyze(text);
+        let word = word_at(&analysis.tree, position)?;
+        let (span, _) = analysis.labels.get(word)?;
+        Some(json!({"uri": uri, "range": range(&analysis.tree, span)}))
+    }
+
+    fn references(
+        &self,
+        uri: &str,
+        text: &str,
+        position: &Value,
+        declaration: bool,
+    ) -> Option<Value> {
+        let analysis = analyze(text);
+        let tree = &analysis.tree;
+        let word = word_at(tree, position)?;
+        analysis.labels.get(word)?;
+        let declarations: Vec<&Span> = tree.lines.iter().flat_map(|l| &l.labels).collect();
+        let locations = tree
+            .tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::Word && tree.text(&t.span) == word)
+            .filter(|t| declaration || !declarations.contains(&&t.span))
+            .map(|t| json!({"uri": uri, "range": range(tree, &t.span)}))
+            .collect();
+        Some(Value::Array(locations))
+    }
+
+    fn completion(&self, text: &str) -> Value {
+        let mnemonics = MNEMONICS.iter().map(|(name, doc)| {
+            json!({
+                "label": name,
+                "kind": KEYWORD_ITEM,
+                "detail": doc.lines().next().unwrap_or(""),
+                "documentation": doc,
+            })
+        });
+        let analysis = analyze(text);
+        let labels = analysis.labels.iter().map(|(label, (span, _))| {
+            let (line, _) = analysis.tree.position(span.start);
+            json!({
+                "label": label,
+                "kind": REFERENCE_ITEM,
+                "detail": format!("line {}", line + 1),
+            })
+        });
+        Value::Array(mnemonics.chain(labels).collect())
+    }
+
+    /// Replace the whole document by its formatted version, if it assembles.
+    fn formatting(&self, text: &str) -> Option<Value> {
+        let formatted = format_source(text).ok()?;
+        let end = text.lines().count() + 1;
+        Some(json!([{
+            "range": {
+                "start": {"line": 0, "character": 0},
+                "end": {"line": end, "character": 0},
+            },
+            "newText": formatted,
+        }]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "start: movi 1,5
+loop: addi 1,1,-1
+      beq 1,0,done
+      beq 0,0,loop
+loop: addi 1,1,100
+done: halt
+      add 1,2
+";
+
+    /// Messages of the server answering `messages`, the document is opened first.
+    fn serve(text: &str, messages: &[Value]) -> Vec<Value> {
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": "file:///a.s", "text": text}},
+        });
+        let input: String = std::iter::once(&open)
+            .chain(messages)
+            .map(|message| {
+                let body = message.to_string();
+                format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+            })
+            .collect();
+        let mut out = Vec::new();
+        LspServer::new().serve(input.as_bytes(), &mut out).unwrap();
+        let mut out = out.as_slice();
+        std::iter::from_fn(|| read_message(&mut out).unwrap()).collect()
+    }
+
+    /// The result of `method` at `line` and `character` of the document.
+    fn request(method: &str, line: usize, character: usize, context: Value) -> Value {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": {
+                "textDocument": {"uri": "file:///a.s"},
+                "position": {"line": line, "character": character},
+                "context": context,
+            },
+        });
+        let mut messages = serve(DOCUMENT, &[request]);
+        assert_eq!(messages.len(), 2);
+        messages.remove(1)["result"].take()
+    }
+
+    fn lines(locations: &Value) -> Vec<u64> {
+        let locations = locations.as_array().unwrap();
+        locations
+            .iter()
+            .map(|l| l["range"]["start"]["line"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let messages = serve(DOCUMENT, &[]);
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics: Vec<(u64, i64, &str)> = messages[0]["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| {
+                let line = d["range"]["start"]["line"].as_u64().unwrap();
+                (
+                    line,
+                    d["severity"].as_i64().unwrap(),
+                    d["message"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (4, ERROR, "Label loop defined twice"),
+                (6, ERROR, "Wrong number of arguments"),
+                (4, WARNING, "Immediate Too BIG : 100"),
+            ]
+        );
+        let duplicate = &messages[0]["params"]["diagnostics"][0]["range"];
+        assert_eq!(duplicate["end"], json!({"line": 4, "character": 4}));
+        // closing the document clears its diagnostics
+        let close = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didClose",
+            "params": {"textDocument": {"uri": "file:///a.s"}},
+        });
+        let messages = serve(DOCUMENT, &[close]);
+        assert_eq!(messages[1]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn hover_and_definition() {
+        let hover = request("textDocument/hover", 3, 15, Value::Null);
+        // the first definition of a duplicate label is the one used
+        assert_eq!(hover["contents"]["value"], "loop: instruction 1, line 2");
+        let hover = request("textDocument/hover", 2, 7, Value::Null);
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("beq rA, rB, label"));
+        let definition = request("textDocument/definition", 3, 14, Value::Null);
+        assert_eq!(definition["uri"], "file:///a.s");
+        assert_eq!(
+            definition["range"],
+            json!({"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 4}})
+        );
+        assert_eq!(
+            request("textDocument/definition", 0, 8, Value::Null),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn references() {
+        let all = request(
+            "textDocument/references",
+            3,
+            14,
+            json!({"includeDeclaration": true}),
+        );
+        assert_eq!(lines(&all), [1, 3, 4]);
+        let uses = request(
+            "textDocument/references",
+            1,
+            2,
+            json!({"includeDeclaration": false}),
+        );
+        assert_eq!(lines(&uses), [3]);
+    }
+
+    #[test]
+    fn completion() {
+        let items = request("textDocument/completion", 0, 0, Value::Null);
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), MNEMONICS.len() + 3);
+        let addi = items.iter().find(|item| item["label"] == "addi").unwrap();
+        assert_eq!(addi["kind"], KEYWORD_ITEM);
+        assert_eq!(addi["detail"], "addi rA, rB, imm");
+        let done = items.iter().find(|item| item["label"] == "done").unwrap();
+        assert_eq!(
+            (&done["kind"], &done["detail"]),
+            (&json!(REFERENCE_ITEM), &json!("line 6"))
+        );
+    }
+
+    #[test]
+    fn formatting() {
+        // a document that does not assemble is left alone
+        assert_eq!(
+            request("textDocument/formatting", 0, 0, Value::Null),
+            Value::Null
+        );
+        let text = "a: nop\n  halt // end";
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/formatting",
+            "params": {"textDocument": {"uri": "file:///a.s"}},
+        });
+        let messages = serve(text, &[request]);
+        assert_eq!(messages[1]["id"], 2);
+        let edit = &messages[1]["result"][0];
+        assert_eq!(edit["newText"], format_source(text).unwrap());
+        assert_eq!(edit["range"]["end"], json!({"line": 3, "character": 0}));
+    }
+
+    #[test]
+    fn unsupported_methods() {
+        let rename = json!({"jsonrpc": "2.0", "id": 7, "method": "textDocument/rename"});
+        let shutdown = json!({"jsonrpc": "2.0", "id": 8, "method": "shutdown"});
+        let messages = serve("halt", &[rename, shutdown]);
+        assert_eq!(messages[1]["id"], 7);
+        assert_eq!(messages[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(
+            messages[1]["error"]["message"],
+            "Unsupported method: textDocument/rename"
+        );
+        assert_eq!(
+            messages[2],
+            json!({"jsonrpc": "2.0", "id": 8, "result": null})
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use crate::RiscResult;
+
+/// How a 16 bit word is written for the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NumberFormat {
+    /// Two's complement, like 0xfff6.
+    Hex,
+    Unsigned,
+    Signed,
+    /// Two's complement, like 0b1111111111110110.
+    Binary,
+}
+
+impl NumberFormat {
+    pub(crate) fn from_name(name: &str) -> RiscResult<NumberFormat> {
+        match name.trim() {
+            "hex" | "x" => Ok(NumberFormat::Hex),
+            "unsigned" | "u" => Ok(NumberFormat::Unsigned),
+            "signed" | "d" => Ok(NumberFormat::Signed),
+            "binary" | "b" => Ok(NumberFormat::Binary),
+            _ => Err(format!(
+                "Unknown number format: {} (hex, unsigned, signed or binary)",
+                name
+            )
+            .into()),
+        }
+    }
+
+    /// Formats separated by commas, like "hex,signed".
+    pub(crate) fn list(spec: &str) -> RiscResult<Vec<NumberFormat>> {
+        spec.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(NumberFormat::from_name)
+            .collect()
+    }
+
+    pub(crate) fn render(self, val: i16) -> String {
+        match self {
+            NumberFormat::Hex => format!("{:#06x}", val as u16),
+            NumberFormat::Unsigned => (val as u16).to_string(),
+            NumberFormat::Signed => val.to_string(),
+            NumberFormat::Binary => format!("{:#018b}", val as u16),
+        }
+    }
+
+    /// Widest rendering of a word, to align columns.
+    pub(crate) fn width(self) -> usize {
+        match self {
+            NumberFormat::Hex => 6,
+            NumberFormat::Unsigned => 5,
+            NumberFormat::Signed => 6,
+            NumberFormat::Binary => 18,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let names = ["hex", "x", "unsigned", "u", "signed", "d", "binary", " b "];
+        let formats: Vec<NumberFormat> = names
+            .iter()
+            .map(|name| NumberFormat::from_name(name).unwrap())
+            .collect();
+        use NumberFormat::*;
+        assert_eq!(
+            formats,
+            [Hex, Hex, Unsigned, Unsigned, Signed, Signed, Binary, Binary]
+        );
+        for bad in ["Hex", "octal", ""] {
+            assert_eq!(
+                NumberFormat::from_name(bad).unwrap_err().to_string(),
+                format!(
+                    "Unknown number format: {} (hex, unsigned, signed or binary)",
+                    bad
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn lists() {
+        use NumberFormat::*;
+        assert_eq!(NumberFormat::list("hex,signed").unwrap(), [Hex, Signed]);
+        assert_eq!(NumberFormat::list(" u , b ,").unwrap(), [Unsigned, Binary]);
+        assert!(NumberFormat::list("").unwrap().is_empty());
+        let error = NumberFormat::list("hex,oct").unwrap_err().to_string();
+        assert!(error.starts_with("Unknown number format: oct"));
+    }
+
+    #[test]
+    fn render() {
+        use NumberFormat::*;
+        let rendered = |val: i16| [Hex, Unsigned, Signed, Binary].map(|format| format.render(val));
+        assert_eq!(
+            rendered(-10),
+            ["0xfff6", "65526", "-10", "0b1111111111110110"]
+        );
+        assert_eq!(rendered(0), ["0x0000", "0", "0", "0b0000000000000000"]);
+        assert_eq!(
+            rendered(i16::MIN),
+            ["0x8000", "32768", "-32768", "0b1000000000000000"]
+        );
+        // the widest values fill the width exactly
+        for format in [Hex, Unsigned, Signed, Binary] {
+            let widest = [i16::MIN, -1, i16::MAX]
+                .iter()
+                .map(|val| format.render(*val).len())
+                .max();
+            assert_eq!(widest, Some(format.width()));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::stack::region;
+use crate::{spec_settings, CustomError, FaultKind, RiscResult};
+use std::ops::RangeInclusive;
+
+/// What the program may do in a protected region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    /// Loads only, like constants or input data.
+    ReadOnly,
+    /// Neither loads nor stores, like guard pages.
+    NoAccess,
+}
+
+/// Memory regions checked on every `lw` and `sw`, devices included.
+///
+/// In unified-memory mode the program takes the first words of memory, one per ROM
+/// index, followed by its `.fill` and `.space` data, and the code words are protected.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Protection {
+    pub(crate) regions: Vec<(RangeInclusive<u16>, Access)>,
+    /// Unified-memory mode, the code region is protected.
+    pub(crate) code: bool,
+}
+
+impl Protection {
+    /// Parse settings among `readonly=<first>-<last>,...`, `noaccess=<first>-<last>,...`
+    /// and `code` for unified-memory mode, like "code;readonly=0x00-0x0f;noaccess=0xf0-0xff".
+    pub(crate) fn from_spec(spec: &str) -> RiscResult<Protection> {
+        let mut protection = Protection::default();
+        for (key, value) in spec_settings(spec) {
+            let value = match (key, value) {
+                ("code", None) => {
+                    protection.code = true;
+                    continue;
+                }
+                (_, Some(value)) => value,
+                (_, None) => return Err(format!("Bad memory protection setting: {}", key).into()),
+            };
+            let access = match key {
+                "readonly" => Access::ReadOnly,
+                "noaccess" => Access::NoAccess,
+                _ => return Err(format!("Unknown memory protection setting: {}", key).into()),
+            };
+            for range in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                protection.regions.push((region(range)?, access));
+            }
+        }
+        Ok(protection)
+    }
+
+    /// Check a load from or a store to `addr`, by a program of `code_size` instructions.
+    pub(crate) fn check(&self, addr: u16, write: bool, code_size: usize) -> RiscResult<()> {
+        let operation = if write { "write to" } else { "read from" };
+        let access = self
+            .regions
+            .iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(_, access)| *access);
+        let (kind, message) = match access {
+            // the instructions are not encoded in memory, their words cannot be used as data
+            _ if self.code && usize::from(addr) < code_size => (
+                FaultKind::AccessViolation { addr: addr.into() },
+                format!(
+                    "Access violation: {} {:#06x}, in the code region",
+                    operation, addr
+                ),
+            ),
+            Some(Access::NoAccess) => (
+                FaultKind::AccessViolation { addr: addr.into() },
+                format!(
+                    "Access violation: {} {:#06x}, a no-access region",
+                    operation, addr
+                ),
+            ),
+            Some(Access::ReadOnly) if write => (
+                FaultKind::WriteProtected { addr: addr.into() },
+                format!("Write to {:#06x}, a read-only region", addr),
+            ),
+            _ => return Ok(()),
+        };
+        Err(CustomError::fault(kind, &message))
+    }
+}
@@ -0,0 +1,118 @@
+use crate::init::InitPolicy;
+use crate::interrupts::Interrupts;
+use crate::{Archtype, Fault, Risc16, RiscResult, SourceMap, RAM_SIZE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Everything needed to resume a Risc16 where it stopped.
+///
+/// Not kept: the mapped devices, the shadow call stack of the subroutines being
+/// executed, and the calling convention, stack and memory protection checks, which
+/// must be set again on the restored processor.
+///
+/// Fields added after the first snapshots are optional, older snapshots restore
+/// with every location counted as written and no source map.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    arch: Archtype,
+    registers: [i16; 8],
+    pc: usize,
+    ram: Vec<i16>,
+    /// RAM when the run started, older snapshots without it compare against `ram`.
+    #[serde(default)]
+    initial_ram: Vec<i16>,
+    #[serde(default)]
+    initial_registers: Option<[i16; 8]>,
+    /// Registers and RAM words written so far, to report reads of the others.
+    #[serde(default)]
+    reg_written: Option<[bool; 8]>,
+    #[serde(default)]
+    ram_written: Vec<bool>,
+    #[serde(default)]
+    uninit_reads: Vec<(usize, String, String)>,
+    instr_count: u32,
+    max_instr: u32,
+    labels: HashMap<String, usize>,
+    strict: bool,
+    #[serde(default)]
+    trace: bool,
+    #[serde(default = "default_init")]
+    init: InitPolicy,
+    #[serde(default)]
+    source_map: SourceMap,
+    /// Interrupt controller configuration and state.
+    #[serde(default)]
+    interrupts: Interrupts,
+    buffer: String,
+    error: Option<Fault>,
+}
+
+fn default_init() -> InitPolicy {
+    InitPolicy::Zero
+}
+
+impl Risc16 {
+    pub(crate) fn to_json(&self) -> RiscResult<String> {
+        let snapshot = Snapshot {
+            arch: self.arch,
+            registers: self.registers,
+            pc: self.pc,
+            ram: self.ram.to_vec(),
+            initial_ram: self.initial_ram.to_vec(),
+            initial_registers: Some(self.initial_registers),
+            reg_written: Some(self.reg_written),
+            ram_written: self.ram_written.to_vec(),
+            uninit_reads: self.uninit_reads.clone(),
+            instr_count: self.instr_count,
+            max_instr: self.max_instr,
+            labels: self.labels.clone(),
+            strict: self.strict,
+            trace: self.trace,
+            init: self.init,
+            source_map: self.source_map.clone(),
+            interrupts: self.interrupts.clone(),
+            buffer: self.buffer.clone(),
+            error: self.error.clone(),
+        };
+        Ok(serde_json::to_string(&snapshot)?)
+    }
+
+    pub(crate) fn from_json(json: &str) -> RiscResult<Risc16> {
+        let snapshot: Snapshot = serde_json::from_str(json)?;
+        let mut proc = Risc16::new(snapshot.arch, snapshot.max_instr, snapshot.strict);
+        proc.registers = snapshot.registers;
+        proc.pc = snapshot.pc;
+        proc.ram = snapshot
+            .ram
+            .try_into()
+            .map_err(|ram: Vec<i16>| format!("Bad memory size in snapshot: {}", ram.len()))?;
+        proc.initial_ram = match snapshot.initial_ram.len() {
+            0 => proc.ram,
+            _ => snapshot.initial_ram.try_into().map_err(|ram: Vec<i16>| {
+                format!("Bad initial memory size in snapshot: {}", ram.len())
+            })?,
+        };
+        proc.initial_registers = snapshot.initial_registers.unwrap_or(proc.registers);
+        proc.reg_written = snapshot.reg_written.unwrap_or([true; 8]);
+        proc.ram_written = match snapshot.ram_written.len() {
+            0 => [true; RAM_SIZE],
+            _ => snapshot
+                .ram_written
+                .try_into()
+                .map_err(|written: Vec<bool>| {
+                    format!("Bad written memory size in snapshot: {}", written.len())
+                })?,
+        };
+        proc.uninit_reads = snapshot.uninit_reads;
+        proc.instr_count = snapshot.instr_count;
+        proc.labels = snapshot.labels;
+        proc.trace = snapshot.trace;
+        proc.init = snapshot.init;
+        proc.source_map = snapshot.source_map;
+        proc.interrupts = snapshot.interrupts;
+        proc.buffer = snapshot.buffer;
+        proc.error = snapshot.error;
+        Ok(proc)
+    }
+}
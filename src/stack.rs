@@ -0,0 +1,105 @@
+use crate::{
+    fit_word, process_register, process_string_args, spec_settings, CustomError, FaultKind,
+    RiscResult,
+};
+use std::ops::{Range, RangeInclusive};
+
+/// Stack region, checked on `lw` and `sw` addressed through the stack pointer or a
+/// register computed from it with `add` or `addi`.
+///
+/// The stack grows down from `top` to `limit`, both included, and must not run
+/// over the `.fill` and `.space` data of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Stack {
+    pub(crate) stack_pointer: usize,
+    /// Highest stack word, popping above it is an underflow.
+    pub(crate) top: u16,
+    /// Lowest stack word, pushing below it is an overflow.
+    pub(crate) limit: u16,
+}
+
+impl Default for Stack {
+    fn default() -> Stack {
+        Stack {
+            stack_pointer: 6,
+            top: 0xff,
+            limit: 0,
+        }
+    }
+}
+
+impl Stack {
+    /// Parse "default", r6 from 0xff down to 0, or settings among `sp=<reg>`,
+    /// `top=<addr>` and `limit=<addr>`, like "sp=r6;top=0x7f;limit=0x40".
+    pub(crate) fn from_spec(spec: &str) -> RiscResult<Stack> {
+        let mut stack = Stack::default();
+        if spec.trim() == "default" {
+            return Ok(stack);
+        }
+        for (key, value) in spec_settings(spec) {
+            let value = value.ok_or_else(|| format!("Bad stack setting: {}", key))?;
+            match key {
+                "sp" => stack.stack_pointer = process_register(value)?,
+                "top" => stack.top = address(value)?,
+                "limit" => stack.limit = address(value)?,
+                _ => return Err(format!("Unknown stack setting: {}", key).into()),
+            }
+        }
+        if stack.limit > stack.top {
+            return Err(format!(
+                "Stack limit {:#06x} above its top {:#06x}",
+                stack.limit, stack.top
+            )
+            .into());
+        }
+        Ok(stack)
+    }
+
+    /// Check an access to `addr` made through the stack pointer, `data` being the
+    /// addresses of the program data.
+    pub(crate) fn check(&self, addr: u16, write: bool, data: Range<usize>) -> RiscResult<()> {
+        let (kind, message) = if write && data.contains(&usize::from(addr)) {
+            (
+                FaultKind::StackCollision { addr: addr.into() },
+                format!("Stack write to {:#06x} collides with program data", addr),
+            )
+        } else if addr < self.limit {
+            (
+                FaultKind::StackOverflow { addr: addr.into() },
+                format!(
+                    "Stack overflow: access to {:#06x}, below the stack limit {:#06x}",
+                    addr, self.limit
+                ),
+            )
+        } else if addr > self.top {
+            (
+                FaultKind::StackUnderflow { addr: addr.into() },
+                format!(
+                    "Stack underflow: access to {:#06x}, above the stack top {:#06x}",
+                    addr, self.top
+                ),
+            )
+        } else {
+            return Ok(());
+        };
+        Err(CustomError::fault(kind, &message))
+    }
+}
+
+pub(crate) fn address(value: &str) -> RiscResult<u16> {
+    process_string_args(value)
+        .and_then(fit_word)
+        .map(|addr| addr as u16)
+        .ok_or_else(|| format!("Bad address: {}", value).into())
+}
+
+/// `first-last`, or a single address.
+pub(crate) fn region(value: &str) -> RiscResult<RangeInclusive<u16>> {
+    match value.split_once('-') {
+        Some((first, last)) => Ok(address(first.trim())?..=address(last.trim())?),
+        None => {
+            let addr = address(value)?;
+            Ok(addr..=addr)
+        }
+    }
+}
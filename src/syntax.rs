@@ -0,0 +1,298 @@
+use std::ops::Range;
+
+/// Byte range in the source text.
+pub(crate) type Span = Range<usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Spaces, tabs and carriage returns.
+    Whitespace,
+    Newline,
+    /// From `//` to the end of the line.
+    Comment,
+    /// Mnemonics, directives, labels, registers and numbers.
+    Word,
+    Colon,
+    Comma,
+    LParen,
+    RParen,
+    Plus,
+    Minus,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StatementKind {
+    Instruction,
+    /// A statement whose name starts with a dot, like `.fill`.
+    Directive,
+}
+
+/// An instruction or a directive with its operands.
+#[derive(Debug, Clone)]
+pub(crate) struct Statement {
+    pub(crate) kind: StatementKind,
+    pub(crate) name: Span,
+    /// Operands separated by commas, with the whitespace inside them, empty ones are skipped.
+    pub(crate) operands: Vec<Span>,
+    /// From the name to the end of the last operand.
+    pub(crate) span: Span,
+}
+
+/// One source line: labels, then an optional statement, then an optional comment.
+#[derive(Debug, Clone)]
+pub(crate) struct Line {
+    /// Line number, starting at 0.
+    pub(crate) number: usize,
+    /// The line without its line break.
+    pub(crate) span: Span,
+    /// Label names, without their colon.
+    pub(crate) labels: Vec<Span>,
+    pub(crate) statement: Option<Statement>,
+    pub(crate) comment: Option<Span>,
+}
+
+/// Lossless concrete syntax tree of an assembly source: every character belongs to
+/// exactly one token, so the tokens give back the source exactly.
+pub(crate) struct SyntaxTree<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) tokens: Vec<Token>,
+    pub(crate) lines: Vec<Line>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    pub(crate) fn parse(source: &'a str) -> SyntaxTree<'a> {
+        let tokens = tokenize(source);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            if token.kind == TokenKind::Newline {
+                lines.push(parse_line(source, &tokens, lines.len(), start..i + 1));
+                start = i + 1;
+            }
+        }
+        if start < tokens.len() {
+            lines.push(parse_line(
+                source,
+                &tokens,
+                lines.len(),
+                start..tokens.len(),
+            ));
+        }
+        let tree = SyntaxTree {
+            source,
+            tokens,
+            lines,
+        };
+        debug_assert_eq!(tree.to_source(), source);
+        tree
+    }
+
+    pub(crate) fn text(&self, span: &Span) -> &'a str {
+        &self.source[span.clone()]
+    }
+
+    /// The source rebuilt from the tokens, identical to the parsed one.
+    pub(crate) fn to_source(&self) -> String {
+        self.tokens.iter().map(|t| self.text(&t.span)).collect()
+    }
+
+    /// The token containing the byte `offset`, if any.
+    pub(crate) fn token_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .find(|t| t.span.start <= offset && offset < t.span.end)
+    }
+
+    /// Line and column, starting at 0, of the byte `offset`. Columns count
+    /// UTF-16 code units, the default position encoding of the LSP.
+    pub(crate) fn position(&self, offset: usize) -> (usize, usize) {
+        match self.lines.iter().rev().find(|l| l.span.start <= offset) {
+            Some(line) => {
+                let text = &self.source[line.span.start..offset];
+                (line.number, text.encode_utf16().count())
+            }
+            None => (0, 0),
+        }
+    }
+
+    /// Byte offset of a line and UTF-16 column, None past the end of the line
+    /// or inside a character.
+    pub(crate) fn offset(&self, line: usize, column: usize) -> Option<usize> {
+        let span = &self.lines.get(line)?.span;
+        let mut units = 0;
+        for (i, c) in self.source[span.clone()].char_indices() {
+            if units == column {
+                return Some(span.start + i);
+            } else if units > column {
+                return None;
+            }
+            units += c.len_utf16();
+        }
+        if units == column {
+            Some(span.end)
+        } else {
+            None
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let single = match c {
+            '\n' => Some(TokenKind::Newline),
+            ',' => Some(TokenKind::Comma),
+            ':' => Some(TokenKind::Colon),
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            '+' => Some(TokenKind::Plus),
+            '-' => Some(TokenKind::Minus),
+            _ => None,
+        };
+        let kind = if let Some(kind) = single {
+            kind
+        } else if source[start..].starts_with("//") {
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            TokenKind::Comment
+        } else if c.is_whitespace() {
+            while chars
+                .next_if(|(_, c)| c.is_whitespace() && *c != '\n')
+                .is_some()
+            {}
+            TokenKind::Whitespace
+        } else {
+            while chars
+                .next_if(|(i, c)| !ends_word(*c) && !source[*i..].starts_with("//"))
+                .is_some()
+            {}
+            TokenKind::Word
+        };
+        let end = chars.peek().map_or(source.len(), |(i, _)| *i);
+        tokens.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+    tokens
+}
+
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || ",:()+-".contains(c)
+}
+
+fn parse_line(source: &str, tokens: &[Token], number: usize, range: Range<usize>) -> Line {
+    let line_tokens = &tokens[range.clone()];
+    let span_start = line_tokens.first().map_or(source.len(), |t| t.span.start);
+    let span_end = line_tokens
+        .iter()
+        .rev()
+        .find(|t| t.kind != TokenKind::Newline)
+        .map_or(span_start, |t| t.span.end);
+    // positions of the significant tokens, whitespace aside
+    let code: Vec<&Token> = line_tokens
+        .iter()
+        .filter(|t| !matches!(t.kind, TokenKind::Whitespace | TokenKind::Newline))
+        .collect();
+    let comment = code
+        .last()
+        .filter(|t| t.kind == TokenKind::Comment)
+        .map(|t| t.span.clone());
+    let code = &code[..code.len() - comment.iter().count()];
+
+    let mut labels = Vec::new();
+    let mut i = 0;
+    while i + 1 < code.len()
+        && code[i].kind == TokenKind::Word
+        && code[i + 1].kind == TokenKind::Colon
+    {
+        labels.push(code[i].span.clone());
+        i += 2;
+    }
+
+    let statement = code.get(i).map(|name| {
+        let kind = if source[name.span.clone()].starts_with('.') {
+            StatementKind::Directive
+        } else {
+            StatementKind::Instruction
+        };
+        let first = line_tokens
+            .iter()
+            .position(|t| t.span.start == name.span.end)
+            .unwrap_or(line_tokens.len());
+        let operands = split_operands(&line_tokens[first..]);
+        let end = operands.last().map_or(name.span.end, |op| op.end);
+        Statement {
+            kind,
+            name: name.span.clone(),
+            span: name.span.start..end,
+            operands,
+        }
+    });
+
+    Line {
+        number,
+        span: span_start..span_end,
+        labels,
+        statement,
+        comment,
+    }
+}
+
+fn split_operands(tokens: &[Token]) -> Vec<Span> {
+    // operands are separated by commas only, so that `4 (2)` or `data - 1` stay in
+    // one piece; whitespace around an operand is left out of its span
+    let mut operands = Vec::new();
+    let mut current: Option<Span> = None;
+    for token in tokens {
+        match token.kind {
+            TokenKind::Newline | TokenKind::Comment => break,
+            TokenKind::Comma => {
+                operands.extend(current.take());
+                continue;
+            }
+            TokenKind::Whitespace => continue,
+            _ => (),
+        }
+        current = Some(match current {
+            Some(span) => span.start..token.span.end,
+            None => token.span.clone(),
+        });
+    }
+    operands.extend(current);
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operands_split_on_commas() {
+        let tree = SyntaxTree::parse("lw 1 , 4 (2) // x\naddi 1,,data - 1,");
+        let operands = |line: usize| -> Vec<&str> {
+            let statement = tree.lines[line].statement.as_ref().unwrap();
+            statement.operands.iter().map(|op| tree.text(op)).collect()
+        };
+        assert_eq!(operands(0), ["1", "4 (2)"]);
+        assert_eq!(operands(1), ["1", "data - 1"]);
+    }
+
+    #[test]
+    fn utf16_columns() {
+        let tree = SyntaxTree::parse("nop\nnop // \u{e9}\u{1f600}x");
+        let x = tree.source.len() - 1;
+        // é is one UTF-16 unit, the emoji two
+        assert_eq!(tree.position(x), (1, 10));
+        assert_eq!(tree.offset(1, 10), Some(x));
+        assert_eq!(tree.offset(1, 9), None);
+        assert_eq!(tree.offset(1, 11), Some(tree.source.len()));
+        assert_eq!(tree.offset(1, 12), None);
+    }
+}
@@ -8,8 +8,9 @@ from flask import (
     flash,
     redirect,
 )
-import librisc16_rs
+import risc16_rs  # install with `pip install .` (or `maturin develop`) from the repo root
 import modules
+import devices
 
 app = Flask(__name__)
 
@@ -19,10 +20,11 @@ def upload():
     if request.method == "POST":
         max_instr = int(request.form.get("exec", 100000))
         test_file = request.form.get("exo", "")
-        archi = request.form.get("archi", "IS0")  # not used yet
+        archi = request.form.get("archi", "IS0")  # only used by the unit page
         unsigned = request.form.get("logic", "unsigned")  # not used yet
         trace_bool = request.form.get("trace", 0) == "1"
         trace = ""
+        device_state = ""
         # print(request.form)
 
         file = request.files.get("file")
@@ -31,31 +33,42 @@ def upload():
             ex = modules.exercise(test_file)
             input_vec, output_vec = ex.get_input_test_vector()
             try:
-                # tests = librisc16_rs.test_batch_py(
+                # tests = risc16_rs.test_batch_py(
                 #     max_instr, trace_bool, text, input_vec
                 # )
-                tests = librisc16_rs.test_batch_par_py(
+                tests = risc16_rs.test_batch_par_py(
                     max_instr, trace_bool, text, input_vec
                 )
                 tests_passed = ex.verify([reg.registers for reg in tests])
                 res = ex.create_report(tests_passed, tests)
-            except BaseException as e:
+            except (risc16_rs.AssemblyError, risc16_rs.ExecutionError) as e:
                 # print(e)
                 res = str(e)
                 trace = "Error!"
         else:
             text = request.form.get("code_area", "")
             try:
-                res, trace = librisc16_rs.run_from_str_py(max_instr, trace_bool, text)
+                proc = risc16_rs.Risc16(max_instr, arch=archi)
+                proc.trace = trace_bool
+                for name, device, first, last in devices.UNIT_DEVICES:
+                    proc.add_device(device(), name, first, last)
+                proc.run(text)
+                res = proc.buffer
+                if proc.console:
+                    res += "Console:\n" + proc.console + "\n"
+                trace = proc.print_state()
+                device_state = "\n".join(
+                    f"{name}: {state}" for name, state in proc.device_states.items()
+                )
                 # print(res, trace)
-            except BaseException as e:
+            except (risc16_rs.AssemblyError, risc16_rs.ExecutionError) as e:
                 # print(e)
                 res = str(e)
                 trace = "Error!"
 
         try:
-            code = librisc16_rs.load_rom_py(text)
-        except BaseException as e:
+            code = risc16_rs.load_rom_py(text)
+        except (risc16_rs.AssemblyError, risc16_rs.ExecutionError) as e:
             # print(e)
             code = str(e)
 
@@ -63,6 +76,7 @@ def upload():
             "tests_results": res,
             "code_content": code,
             "end_state": trace,
+            "device_state": device_state,
         }
         return context
 
@@ -0,0 +1,42 @@
+# Example peripherals that can be mapped on a risc16_rs.Risc16 with add_device.
+# A device provides read(addr) and write(addr, value), and optionally state()
+# which is rendered in the web page after a run.
+
+
+class LedBar:
+    """8 LEDs driven by the low byte of the word written."""
+
+    def __init__(self):
+        self.value = 0
+
+    def read(self, addr):
+        return self.value
+
+    def write(self, addr, value):
+        self.value = value & 0xFF
+
+    def state(self):
+        return "".join("●" if self.value >> i & 1 else "○" for i in range(7, -1, -1))
+
+
+class SevenSegment:
+    """Hex digits, one per address, showing the low nibble of the word written."""
+
+    def __init__(self, digits=4):
+        self.digits = [0] * digits
+
+    def read(self, addr):
+        return self.digits[addr % len(self.digits)]
+
+    def write(self, addr, value):
+        self.digits[addr % len(self.digits)] = value & 0xF
+
+    def state(self):
+        return "".join(f"{d:X}" for d in self.digits)
+
+
+# devices mapped in the unit test page: (name, factory, first address, last address)
+UNIT_DEVICES = [
+    ("leds", LedBar, 0xFFE0, 0xFFE0),
+    ("7seg", SevenSegment, 0xFFE4, 0xFFE7),
+]
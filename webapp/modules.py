@@ -73,16 +73,21 @@ class exercise:
 
 
 # function used to serialize risc 16 proc objects to dict so that
-# flask can json serialize it.
+# flask can json serialize it, with only the fields the page shows.
 def risc16_to_json(proc, test_bool, test_str):
+    error = proc.error
+    if error is not None:
+        error = {
+            "kind": error.kind,
+            "pc": error.pc,
+            "message": error.message,
+            "source": error.source,
+        }
     return {
-        "buffer": proc.buffer,
-        "instr_count": proc.instr_count,
-        "labels": proc.labels,
-        "pc": proc.pc,
-        "registers": proc.registers,
         "test": test_bool,
         "result_str": test_str,
+        "instr_count": proc.instr_count,
+        "error": error,
     }
 
 
@@ -15,18 +15,33 @@ function processRisc(event) {
         end_state.textContent = d.end_state
     }
 
+    let device_state = document.getElementById("device_state");
+    if (device_state) {
+        device_state.textContent = d.device_state
+    }
+
     let tests = document.getElementById("tests_results");
     if (tests && Array.isArray(d.tests_results)) {
         tests.innerHTML = `<p>${d.tests_results.map(t =>
             t.test
                 ? `👍 ${t.result_str} (${t.instr_count} instruction(s))`
-                : `❌ ${t.result_str} (${t.instr_count} instruction(s))`).join('</p><p>')}</p>`
+                : `❌ ${t.result_str} (${t.instr_count} instruction(s))${errorStr(t.error)}`).join('</p><p>')}</p>`
     } else if (tests) {
         tests.textContent = d.tests_results
     }
 
 }
 
+// Explain why a test run stopped before reaching HALT
+function errorStr(error) {
+    if (!error) {
+        return ""
+    }
+    return error.kind === "InstrLimit"
+        ? ` — infinite loop? stopped at instruction ${error.pc}: ${error.source}`
+        : ` — crashed at instruction ${error.pc}: ${error.message} ${error.source}`
+}
+
 // Process syntax highlighting
 function highlight() {
     const patterns = {
@@ -37,7 +52,7 @@ function highlight() {
             },
             {
                 name: 'instr',
-                match: /^(nop|halt|reset|addi|add|nand|movi|lui|lw|sw|beq|jalr)/i
+                match: /^(nop|halt|reset|addi|add|nand|movi|lui|lw|sw|beq|jalr|reti)/i
             },
             {
                 name: 'label',